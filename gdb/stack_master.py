@@ -0,0 +1,284 @@
+# gdb support for stack-master
+#
+# load it from a gdb started with `rust-gdb` (the printers of std are used to read the collections of the crate):
+#
+#     source path/to/stack-master/gdb/stack_master.py
+#
+# - `print` shows a `Stack` as its variant and length, like `Boxed(len=1184, id=#3)` or `Empty(entry=...)`
+# - `info coroutines` lists the coroutines of every docked thread, like `Stack::dump_coroutines` does
+# - `coroutine bt <id>` prints the backtrace of a suspended coroutine
+#
+# the dock of a thread is found through the list of docks in `stack_master::dock::DEBUG_DOCKS`
+#
+# gdb reads the types and statics of the crate from DWARF, which `i686-pc-windows-msvc` does not emit (its debug info is a pdb, that gdb can not read),
+# so the program must be built for `i686-pc-windows-gnu`
+
+import struct
+
+import gdb
+import gdb.printing
+
+from gdb_providers import (
+    StdVecDequeProvider,
+    StdVecProvider,
+    children_of_btree_map,
+    unwrap_unique_or_non_null,
+)
+
+
+def variant(value):
+    """the name and the fields of the active variant of a rust enum"""
+    fields = [field for field in value.type.fields() if not field.artificial]
+    if len(fields) != 1:
+        raise gdb.GdbError("this gdb can not tell the variant of {}, a newer one is needed".format(value.type))
+    return fields[0].name, value[fields[0]]
+
+
+def option(value):
+    """the value inside of an `Option`, `None` for `None`"""
+    name, fields = variant(value)
+    return fields["__0"] if name == "Some" else None
+
+
+def rc_value(rc):
+    """the value an `Rc` points to"""
+    return unwrap_unique_or_non_null(rc["ptr"]).dereference()["value"]
+
+
+def read(address, length):
+    return bytes(gdb.selected_inferior().read_memory(address, length))
+
+
+def read_fat(pointer):
+    """the bytes a `&[u8]`, a `&str` or a `Box<[u8]>` points to"""
+    if "pointer" in [field.name for field in pointer.type.fields()]:
+        pointer = pointer["pointer"]
+    return read(int(pointer["data_ptr"]), int(pointer["length"]))
+
+
+def read_string(string):
+    vec = string["vec"]
+    data = unwrap_unique_or_non_null(vec["buf"]["inner"]["ptr"])
+    return read(int(data), int(vec["len"])).decode("utf-8", "replace")
+
+
+def read_location(location):
+    names = [field.name for field in location.type.fields()]
+    file = read_fat(location["file" if "file" in names else "filename"]).rstrip(b"\0")
+    return "{}:{}:{}".format(file.decode("utf-8", "replace"), int(location["line"]), int(location["col"]))
+
+
+def symbol(pc):
+    block = gdb.block_for_pc(pc)
+    while block is not None and block.function is None:
+        block = block.superblock
+    return block.function.print_name if block is not None else hex(pc)
+
+
+def lz_decompress(data):
+    """the same as `Lz::decompress`"""
+    out = bytearray()
+    i = 0
+    while i < len(data):
+        token = data[i]
+        i += 1
+        if token & 0x80 == 0:
+            out += data[i : i + token + 1]
+            i += token + 1
+        else:
+            offset = data[i] | data[i + 1] << 8
+            i += 2
+            start = len(out) - offset
+            # byte by byte, the source can overlap the bytes being written
+            for index in range(start, start + (token & 0x7F) + 4):
+                out.append(out[index])
+    return bytes(out)
+
+
+def stack_bytes(stack):
+    """the bytes of a suspended stack, `None` if it never ran"""
+    name, fields = variant(stack["inner"])
+    if name == "Empty":
+        return None
+    if name == "Boxed":
+        vec = fields["__0"]
+        return read(int(unwrap_unique_or_non_null(vec["buf"]["inner"]["ptr"])), int(vec["len"]))
+    if name == "Inline":
+        return read(int(fields["bytes"].address), int(fields["len"]))
+    if name == "Tracked":
+        data = read_fat(fields["bytes"])
+        return data[len(data) - int(fields["len"]) :]
+    if name == "External":
+        return read(int(fields["data"]), int(fields["len"]))
+    if name == "Live":
+        live = rc_value(fields["__0"])
+        copy = option(live["copy"]["value"]["value"])
+        if copy is not None:
+            return stack_bytes(copy)
+        return read(int(live["data"]), int(live["len"]))
+    if name == "Chunked":
+        chunks = [read(int(rc["ptr"]["pointer"]["data_ptr"]) + 8, int(rc["ptr"]["pointer"]["length"]))
+                  for _, rc in StdVecProvider(fields["chunks"]).children()]
+        return b"".join(reversed(chunks))
+    if name == "Compressed":
+        data = lz_decompress(read_fat(fields["bytes"]))
+        if len(data) != int(fields["len"]):
+            raise gdb.GdbError("the stack was compressed with a codec other than Lz")
+        return data
+    raise gdb.GdbError("the bytes of a {} stack can not be read from gdb".format(name))
+
+
+def docks():
+    """the `(thread id, dock)` of every thread that is docked"""
+    try:
+        link = gdb.parse_and_eval("stack_master::dock::DEBUG_DOCKS")
+    except gdb.error:
+        raise gdb.GdbError("stack_master::dock::DEBUG_DOCKS was not found, is stack-master linked with debug info?")
+    while int(link) != 0:
+        link = link.dereference()
+        dock = option(link["dock"].dereference()["value"]["value"])
+        if dock is not None:
+            yield int(link["thread"]), dock
+        link = link["next"]
+
+
+def stacks(dock):
+    """the stacks in the run queue and the parked stacks of the dock"""
+    for _, stack in StdVecDequeProvider(dock["run_queue"]).children():
+        yield stack
+    for _, stack in children_of_btree_map(dock["parked"]):
+        yield stack
+
+
+class StackPrinter:
+    def __init__(self, value):
+        self._value = value
+
+    def to_string(self):
+        name, fields = variant(self._value["inner"])
+        if name == "Empty":
+            details = "entry={}".format(symbol(int(fields["f"])))
+        elif name == "Boxed":
+            details = "len={}".format(int(fields["__0"]["len"]))
+        elif name == "Live":
+            details = "len={}".format(int(rc_value(fields["__0"])["len"]))
+        else:
+            details = "len={}".format(int(fields["len"]))
+        id = option(self._value["id"])
+        if id is not None:
+            details += ", id=#{}".format(int(id["__0"]))
+        return "{}({})".format(name, details)
+
+
+class SendStackPrinter:
+    def __init__(self, value):
+        self._value = value
+
+    def to_string(self):
+        return "SendStack({})".format(StackPrinter(self._value["stack"]).to_string())
+
+
+class CoroutineIdPrinter:
+    def __init__(self, value):
+        self._value = value
+
+    def to_string(self):
+        return "#{}".format(int(self._value["__0"]))
+
+
+class InfoCoroutines(gdb.Command):
+    """list the coroutines of every docked thread
+
+usage: info coroutines"""
+
+    def __init__(self):
+        super().__init__("info coroutines", gdb.COMMAND_STATUS)
+
+    def invoke(self, argument, from_tty):
+        for thread, dock in docks():
+            coroutines = list(children_of_btree_map(dock["registry"]["coroutines"]))
+            print("thread {}: {} coroutines".format(thread, len(coroutines)))
+            for _, info in coroutines:
+                line = "  coroutine #{}".format(int(info["id"]["__0"]))
+                name = option(info["name"])
+                if name is not None:
+                    line += " {!r}".format(read_string(name))
+                line += " {}, {} bytes".format(str(info["state"]).rsplit("::", 1)[-1], int(info["stack_size"]))
+                location = option(info["location"])
+                if location is not None:
+                    line += ", created at {}".format(read_location(location.dereference()))
+                print(line)
+
+
+class Coroutine(gdb.Command):
+    """commands for the coroutines of stack-master"""
+
+    def __init__(self):
+        super().__init__("coroutine", gdb.COMMAND_STACK, prefix=True)
+
+
+class CoroutineBacktrace(gdb.Command):
+    """print the backtrace of a suspended coroutine
+
+usage: coroutine bt ID [BT ARGUMENTS]
+
+the bytes of the stack are written to where the stack lands and the registers of the current thread are pointed at them,
+everything is put back once the backtrace is printed, so this needs a live process
+
+while the backtrace is printed the dock of the coroutine holds the suspended bytes instead of the frames of whatever runs there,
+so this refuses to run unless every thread of the process is stopped, and the process must not be resumed from inside of it (like from a breakpoint command)"""
+
+    def __init__(self):
+        super().__init__("coroutine bt", gdb.COMMAND_STACK)
+
+    def invoke(self, argument, from_tty):
+        arguments = argument.split(None, 1)
+        if not arguments:
+            raise gdb.GdbError("usage: coroutine bt ID [BT ARGUMENTS]")
+        id = int(arguments[0].lstrip("#"))
+        inferior = gdb.selected_inferior()
+        if gdb.parameter("non-stop") or not all(thread.is_stopped() for thread in inferior.threads()):
+            raise gdb.GdbError("coroutine bt overwrites the stack of a dock, every thread must be stopped (and non-stop mode off)")
+        stack = next(
+            (stack for _, dock in docks() for stack in stacks(dock)
+             if option(stack["id"]) is not None and int(option(stack["id"])["__0"]) == id),
+            None,
+        )
+        if stack is None:
+            raise gdb.GdbError("coroutine #{} is not in a run queue or parked".format(id))
+        data = stack_bytes(stack)
+        if data is None:
+            raise gdb.GdbError("coroutine #{} never ran".format(id))
+
+        # the layout suspend leaves at the end of the stack: edi, esi, ebx, ebp and the return address
+        landing = int(stack["start"]) - len(data)
+        edi, esi, ebx, ebp, eip = struct.unpack_from("<5I", data)
+        gdb.execute("select-frame 0")
+        frame = gdb.selected_frame()
+        registers = {name: int(frame.read_register(name)) for name in ("eip", "esp", "ebp", "ebx", "esi", "edi")}
+        memory = read(landing, len(data))
+        try:
+            inferior.write_memory(landing, data)
+            # as if the call to suspend just returned
+            for name, value in (("edi", edi), ("esi", esi), ("ebx", ebx), ("ebp", ebp), ("esp", landing + 20), ("eip", eip)):
+                gdb.execute("set ${} = {}".format(name, value))
+            gdb.execute("bt " + (arguments[1] if len(arguments) > 1 else ""))
+        finally:
+            gdb.execute("select-frame 0")
+            for name, value in registers.items():
+                gdb.execute("set ${} = {}".format(name, value))
+            inferior.write_memory(landing, memory)
+
+
+def build_pretty_printer():
+    printer = gdb.printing.RegexpCollectionPrettyPrinter("stack_master")
+    printer.add_printer("Stack", "^stack_master::Stack$", StackPrinter)
+    printer.add_printer("SendStack", "^stack_master::send::SendStack$", SendStackPrinter)
+    printer.add_printer("CoroutineId", "^stack_master::registry::CoroutineId$", CoroutineIdPrinter)
+    return printer
+
+
+gdb.printing.register_pretty_printer(gdb.current_objfile(), build_pretty_printer())
+InfoCoroutines()
+Coroutine()
+CoroutineBacktrace()
@@ -0,0 +1,148 @@
+//! annotations that keep AddressSanitizer in sync with the stack switches of the crate, with the `asan` feature
+//!
+//! asan keeps a shadow of every stack to find the redzones between locals, and a fake stack per thread for the locals that may outlive their frame,
+//! both go out of sync when the bytes of a stack are copied out and landed somewhere else, so every switch is announced with the fiber api of asan,
+//! and every stack that lands is unpoisoned
+//!
+//! the program must be built with `-Zsanitizer=address` (or linked with an asan runtime some other way),
+//! without the feature every function here does nothing
+
+use std::ffi::c_void;
+
+/// the fake stack of a context that was switched away from, given back to asan once the context runs again
+pub(crate) type FakeStack = *mut c_void;
+
+#[cfg(feature = "asan")]
+unsafe extern "C" {
+    fn __sanitizer_start_switch_fiber(
+        fake_stack_save: *mut FakeStack,
+        bottom: *const c_void,
+        size: usize,
+    );
+    fn __sanitizer_finish_switch_fiber(
+        fake_stack_save: FakeStack,
+        bottom_old: *mut *const c_void,
+        size_old: *mut usize,
+    );
+    fn __asan_unpoison_memory_region(addr: *const c_void, size: usize);
+}
+
+/// the lowest address and the size of a stack
+#[cfg(feature = "asan")]
+#[derive(Clone, Copy)]
+struct Bounds(*const c_void, usize);
+
+#[cfg(feature = "asan")]
+thread_local! {
+    /// the bounds of the stack of the current dock, and of the stack it was entered from
+    static BOUNDS: std::cell::Cell<Option<(Bounds, Bounds)>> = const { std::cell::Cell::new(None) };
+}
+
+#[cfg(feature = "asan")]
+fn thread_bounds() -> Bounds {
+    let (mut low, mut high) = (0, 0);
+    unsafe { crate::sys::GetCurrentThreadStackLimits(&mut low, &mut high) };
+    Bounds(low as _, high - low)
+}
+
+/// what [`enter_dock`] hands to [`leave_dock`]
+pub(crate) struct Entered {
+    #[cfg(feature = "asan")]
+    fake_stack: FakeStack,
+    #[cfg(feature = "asan")]
+    previous: Option<(Bounds, Bounds)>,
+}
+
+/// about to switch to the stack of a dock, which is the region from `limit` to `top` if there is one, and the stack of the thread otherwise
+pub(crate) fn enter_dock(region: Option<(*const u8, *const u8)>) -> Entered {
+    #[cfg(feature = "asan")]
+    {
+        let host = BOUNDS.get().map_or_else(thread_bounds, |(dock, _)| dock);
+        let dock = region.map_or(host, |(limit, top)| {
+            Bounds(limit as _, top as usize - limit as usize)
+        });
+        let mut entered = Entered {
+            fake_stack: std::ptr::null_mut(),
+            previous: BOUNDS.replace(Some((dock, host))),
+        };
+        unsafe { __sanitizer_start_switch_fiber(&mut entered.fake_stack, dock.0, dock.1) };
+        entered
+    }
+    #[cfg(not(feature = "asan"))]
+    {
+        let _ = region;
+        Entered {}
+    }
+}
+
+/// the dock returned, back on the stack [`enter_dock`] was called from
+pub(crate) fn leave_dock(entered: Entered) {
+    #[cfg(feature = "asan")]
+    unsafe {
+        BOUNDS.set(entered.previous);
+        __sanitizer_finish_switch_fiber(
+            entered.fake_stack,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        );
+    }
+    #[cfg(not(feature = "asan"))]
+    let _ = entered;
+}
+
+/// an entry function is about to return, which returns from the dock
+pub(crate) fn returning_from_dock() {
+    #[cfg(feature = "asan")]
+    if let Some((_, host)) = BOUNDS.get() {
+        unsafe { __sanitizer_start_switch_fiber(std::ptr::null_mut(), host.0, host.1) };
+    }
+}
+
+/// the current context is being suspended, `save` gets its fake stack
+///
+/// whatever runs on the dock until the next switch (like the callback of the suspend) is a context of its own
+pub(crate) fn suspending(save: &mut FakeStack) {
+    #[cfg(feature = "asan")]
+    if let Some((dock, _)) = BOUNDS.get() {
+        unsafe {
+            __sanitizer_start_switch_fiber(save, dock.0, dock.1);
+            __sanitizer_finish_switch_fiber(
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            );
+        }
+    }
+    #[cfg(not(feature = "asan"))]
+    let _ = save;
+}
+
+/// something is about to land or restart, the current context never runs again
+pub(crate) fn leaving() {
+    #[cfg(feature = "asan")]
+    if let Some((dock, _)) = BOUNDS.get() {
+        unsafe { __sanitizer_start_switch_fiber(std::ptr::null_mut(), dock.0, dock.1) };
+    }
+}
+
+/// a context runs again after a switch, with the fake stack [`suspending`] saved for it, null for a context that just started
+pub(crate) fn arrived(fake_stack: FakeStack) {
+    #[cfg(feature = "asan")]
+    if BOUNDS.get().is_some() {
+        unsafe {
+            __sanitizer_finish_switch_fiber(fake_stack, std::ptr::null_mut(), std::ptr::null_mut())
+        };
+    }
+    #[cfg(not(feature = "asan"))]
+    let _ = fake_stack;
+}
+
+/// clears the redzones asan left in the `len` bytes at `data`, the frames there are not the ones it poisoned them for
+pub(crate) fn unpoison(data: *const u8, len: usize) {
+    #[cfg(feature = "asan")]
+    unsafe {
+        __asan_unpoison_memory_region(data as _, len)
+    };
+    #[cfg(not(feature = "asan"))]
+    let _ = (data, len);
+}
@@ -1,12 +1,53 @@
 use std::arch::naked_asm;
+use std::cell::Cell;
 
 #[cfg(not(all(target_arch = "x86", target_pointer_width = "32")))]
 compile_error! {"This crate only supports 32-bit x86 targets!"}
 
-// TODO! this global is very unsafe and is currently leaking unsafety through the safe interface through data-races (Stack::dock)
+// the `.cfi` directives below are the unwind info of the functions, for debuggers and profilers that unwind with `.eh_frame`, like gdb on the gnu targets
 //
-// it should not be too hard to turn this into a thread_local, just have an utility function the asm can call to get a pointer to it
-static mut STACK_START: *const u8 = std::ptr::null();
+// on the msvc targets unwinding is described by the pdb (and by the SEH chain on x86), link.exe and the debuggers that read pdbs ignore `.eh_frame`,
+// and a pdb cannot be given unwind info from inline asm, so there they are left out and unwinding stops at these functions
+//
+// `.cfi_def_cfa_offset` only takes positive offsets, while the stack of `suspend` is rearranged the cfa is below esp, those are written as
+// `DW_CFA_def_cfa_offset_sf` with `.cfi_escape`, whose operand is the offset divided by the data alignment factor of x86 (-4)
+
+/// unwind info, left out on the targets that do not use it, see above
+#[cfg(target_env = "gnu")]
+macro_rules! cfi {
+    ($($directive:literal),* $(,)?) => {
+        concat!($($directive, "\n"),*)
+    };
+}
+
+/// unwind info, left out on the targets that do not use it, see above
+#[cfg(not(target_env = "gnu"))]
+macro_rules! cfi {
+    ($($directive:literal),* $(,)?) => {
+        ""
+    };
+}
+
+thread_local! {
+    /// the address of the dock of the current thread, set by `dock`
+    static STACK_START: Cell<*const u8> = const { Cell::new(std::ptr::null()) };
+}
+
+/// returns a pointer to the `STACK_START` of the current thread, this is how the asm below reaches it
+///
+/// like any other stdcall function it only clobbers `eax`, `ecx` and `edx`, but it needs a working stack
+extern "stdcall" fn stack_start() -> *mut *const u8 {
+    STACK_START.with(Cell::as_ptr)
+}
+
+/// the address of the dock of the current thread, null outside of a dock
+pub(crate) fn current_stack_start() -> *const u8 {
+    STACK_START.with(Cell::get)
+}
+
+pub(crate) fn set_stack_start(start: *const u8) {
+    STACK_START.with(|cell| cell.set(start))
+}
 
 /// ### the purpose of this function:
 ///
@@ -19,7 +60,7 @@ static mut STACK_START: *const u8 = std::ptr::null();
 /// * first, it removes its own return address and arguments (`f`, `a`) from the stack, placing them into registers for later use.
 /// * it then pushes the original return address back onto the stack, followed by all standard callee-saved registers (`ebp`, `ebx`, `esi`, `edi`). this creates a predictable, restorable stack frame.
 /// * next, it pushes the argument `a` for the function `f` that it is about to call.
-/// * it then calculates the memory address of this argument on the stack (`esp+4`) and stores this location in the global `STACK_START` static. this address serves as the fixed "dock" point, or the highest memory address for all subsequent stack manipulations.
+/// * it then calculates the memory address of this argument on the stack (`esp+4`) and stores this location in the thread local `STACK_START`. this address serves as the fixed "dock" point, or the highest memory address for all subsequent stack manipulations.
 /// * it calls the provided function `f` with the argument `a`.
 /// * once `f` returns, it pops the callee-saved registers to restore the machine state and then executes a `ret` to return to its original caller, passing along the result from `f`.
 #[unsafe(naked)]
@@ -28,29 +69,51 @@ pub(crate) unsafe extern "stdcall" fn dock<A, B>(
     a: *mut A,
 ) -> *mut B {
     naked_asm!(
+        // the unwind info (the .cfi directives) tracks the distance from esp to the frame of the caller,
+        // every stack that lands returns here, so this is what lets debuggers unwind from docked code into the host
+        cfi!(".cfi_startproc"),
         "push ebp",
+        cfi!(".cfi_def_cfa_offset 8", ".cfi_offset ebp, -8"),
 
         "mov eax, [esp+8]", // read `f`
         "mov ecx, [esp+12]", // read `a`
 
         "push ebx",
+        cfi!(".cfi_def_cfa_offset 12", ".cfi_offset ebx, -12"),
         "push esi",
+        cfi!(".cfi_def_cfa_offset 16", ".cfi_offset esi, -16"),
         "push edi",
+        cfi!(".cfi_def_cfa_offset 20", ".cfi_offset edi, -20"),
 
         // --- install SEH registration record ---
         // Push handler then push old chain; so memory at [esp] = Next (old FS), [esp+4] = Handler
         "lea edx, {_except_handler_noop}", // Handler
         "push edx",  // Handler
+        cfi!(".cfi_def_cfa_offset 24"),
         "push dword ptr fs:[0]",                  // Next (old chain head)
+        cfi!(".cfi_def_cfa_offset 28"),
         "mov dword ptr fs:[0], esp",             // Link new record into FS:[0]
         // Now fs:[0] points to our EXCEPTION_REGISTRATION_RECORD on the stack.
 
+        // get the address of STACK_START, keeping `f` and `a` safe from the call
+        "push eax",
+        cfi!(".cfi_def_cfa_offset 32"),
+        "push ecx",
+        cfi!(".cfi_def_cfa_offset 36"),
+        "call {stack_start}",
+        "pop ecx",
+        cfi!(".cfi_def_cfa_offset 32"),
+        "pop edx",
+        cfi!(".cfi_def_cfa_offset 28"),
+
         // store the current esp into STACK_START (-8 to account for the argument and return address pushed by call)
         "lea ebx, [esp-8]",
-        "mov [{stack_start}], ebx",
+        "mov [eax], ebx",
 
         "push ecx", // push the argument `a` for `f`
-        "call eax", // call `f`
+        cfi!(".cfi_def_cfa_offset 32"),
+        "call edx", // call `f`
+        cfi!(".cfi_def_cfa_offset 28"), // `f` popped its argument
 
         // --- normal return path: restore chain and registers ---
         // Note: we must unlink our SEH record before popping the callee-saved regs
@@ -63,14 +126,20 @@ pub(crate) unsafe extern "stdcall" fn dock<A, B>(
 
         // pop the registration record off the stack (handler + oldFS)
         "add esp, 8", // discard the two dwords we pushed for the record
+        cfi!(".cfi_def_cfa_offset 20"),
 
         "pop edi",
+        cfi!(".cfi_def_cfa_offset 16", ".cfi_restore edi"),
         "pop esi",
+        cfi!(".cfi_def_cfa_offset 12", ".cfi_restore esi"),
         "pop ebx",
+        cfi!(".cfi_def_cfa_offset 8", ".cfi_restore ebx"),
 
         "pop ebp",
+        cfi!(".cfi_def_cfa_offset 4", ".cfi_restore ebp"),
         "ret 8",
-        stack_start = sym STACK_START,
+        cfi!(".cfi_endproc"),
+        stack_start = sym stack_start,
         _except_handler_noop = sym _except_handler_noop,
     )
 }
@@ -78,8 +147,66 @@ pub(crate) unsafe extern "stdcall" fn dock<A, B>(
 #[unsafe(naked)]
 pub(crate) unsafe extern "stdcall" fn _except_handler_noop() -> usize {
     naked_asm!(
+        cfi!(".cfi_startproc"),
         "xor eax, eax",
         "ret",
+        cfi!(".cfi_endproc"),
+    )
+}
+
+/// ### the purpose of this function:
+///
+/// it does the same as `dock`, but on a separate block of memory (a "region") instead of the stack it was called on.
+///
+/// because the dock always starts at the top of the region, stacks suspended inside of it can be resumed by a later call to this function with the same region.
+///
+/// ### what this function does:
+///
+/// * it saves `ebp` and uses it to remember where the host stack is, so the arguments can still be read after the switch.
+/// * it saves the stack bounds of the thread (`StackBase` at `fs:[4]` and `StackLimit` at `fs:[8]`) on the host stack and replaces them with `top` and `limit`, so that stack probes and exception dispatching accept the region as the stack of the thread.
+/// * it sets `esp` to `top`, pushes the host `ebp` and then calls `dock` with `f` and `a`.
+/// * once `dock` returns, it pops the host `ebp`, switches `esp` back to the host stack, restores the stack bounds and returns the result of `dock`.
+#[unsafe(naked)]
+pub(crate) unsafe extern "stdcall" fn dock_on<A, B>(
+    f: unsafe extern "stdcall" fn(*mut A) -> *mut B,
+    a: *mut A,
+    top: *mut u8,
+    limit: *mut u8,
+) -> *mut B {
+    naked_asm!(
+        cfi!(".cfi_startproc"),
+        "push ebp",
+        cfi!(".cfi_def_cfa_offset 8", ".cfi_offset ebp, -8"),
+        "mov ebp, esp", // the arguments are now at [ebp+8] onwards
+        cfi!(".cfi_def_cfa_register ebp"), // and the frame of the caller is found through ebp, even while on the region
+
+        // save the stack bounds of the thread on the host stack
+        "push dword ptr fs:[4]", // StackBase
+        "push dword ptr fs:[8]", // StackLimit
+
+        "mov eax, [ebp+16]", // read `top`
+        "mov ecx, [ebp+20]", // read `limit`
+        "mov dword ptr fs:[4], eax",
+        "mov dword ptr fs:[8], ecx",
+
+        "mov esp, eax", // switch to the region
+        "push ebp", // remember where the host stack is
+
+        "push dword ptr [ebp+12]", // push the argument `a` for `dock`
+        "push dword ptr [ebp+8]", // push the function `f` for `dock`
+        "call {dock}", // call `dock`, it pops its own arguments
+
+        // note that we cannot use eax as that is storing the result of the call above
+        "pop ebp", // the host stack
+        "lea esp, [ebp-8]", // switch back to the host stack, right where the stack bounds were saved
+        "pop dword ptr fs:[8]",
+        "pop dword ptr fs:[4]",
+
+        "pop ebp",
+        cfi!(".cfi_def_cfa esp, 4", ".cfi_restore ebp"),
+        "ret 16",
+        cfi!(".cfi_endproc"),
+        dock = sym dock::<A, B>,
     )
 }
 
@@ -126,7 +253,7 @@ pub(crate) unsafe extern "stdcall" fn dock<A, B>(
 ///
 /// ### what this function does:
 ///
-/// * it begins by removing its own return address and arguments (`f`, `a`) from the stack.
+/// * it begins by getting the address of `STACK_START`, then removes its own return address and arguments (`f`, `a`) from the stack.
 /// * it then forcefully resets the stack pointer (`esp`) to the address stored in `STACK_START`. this action instantly abandons the entire current call stack.
 /// * it overwrites the argument slot on the newly reset stack (`[esp+4]`) with its own argument, `a`.
 /// * finally, it performs a tail call by `jmp`ing to the provided function `f`, which will now execute on the clean stack.
@@ -140,13 +267,18 @@ pub(crate) unsafe extern "stdcall" fn restart<A, B>(
     a: *mut A,
 ) -> ! {
     naked_asm!(
+        cfi!(".cfi_startproc"),
+        "call {stack_start}",       // get the address of STACK_START while the stack is still usable
         "add esp, 4",               // pop the return address
+        cfi!(".cfi_undefined eip"), // the frame of the caller is being discarded, there is nothing to unwind to
         "pop edx",                  // pop the function `f`
         "pop ecx",                  // pop the argument `a`
-        "mov esp, [{stack_start}]", // restore the stack to the start
+        "mov esp, [eax]",           // restore the stack to the start
+        cfi!(".cfi_def_cfa_offset 4", ".cfi_offset eip, -4"), // which holds the return address into `dock`
         "mov [esp+4], ecx",         // change the argument to the new one
         "jmp edx",                  // jmp to `f` (tail call)
-        stack_start = sym STACK_START,
+        cfi!(".cfi_endproc"),
+        stack_start = sym stack_start,
     )
 }
 
@@ -171,96 +303,241 @@ pub(crate) unsafe extern "stdcall" fn suspend<A>(
     a: *mut A,
 ) {
     naked_asm!(
+        // the frame of the caller starts where it did before it pushed the arguments (at the original esp + 4),
+        // which is below esp for a moment, since the arguments are popped before the registers are pushed
+        cfi!(".cfi_startproc"),
         // remove things from the stack so we can prepare it for suspend
         "pop eax", // pop the return address
+        cfi!(".cfi_def_cfa_offset 0", ".cfi_register eip, eax"),
         "pop edx", // pop the function
         "pop ecx", // pop the argument
+        cfi!(".cfi_escape 0x13, 0x02"), // DW_CFA_def_cfa_offset_sf, -8 in units of the data alignment factor (-4)
         // push callee saved registers
         "push eax", // the return address
+        cfi!(".cfi_escape 0x13, 0x01", ".cfi_offset eip, 4"), // a cfa offset of -4
         "push ebp",
+        cfi!(".cfi_def_cfa_offset 0", ".cfi_offset ebp, 0"),
         "push ebx",
+        cfi!(".cfi_def_cfa_offset 4", ".cfi_offset ebx, -4"),
         "push esi",
+        cfi!(".cfi_def_cfa_offset 8", ".cfi_offset esi, -8"),
         "push edi",
+        cfi!(".cfi_def_cfa_offset 12", ".cfi_offset edi, -12"),
         // store the end of the stack to a register
         "mov esi, esp",
+        // get the address of STACK_START, keeping the function and the argument safe from the call
+        "push ecx",
+        cfi!(".cfi_def_cfa_offset 16"),
+        "push edx",
+        cfi!(".cfi_def_cfa_offset 20"),
+        "call {stack_start}",
+        "pop edx",
+        cfi!(".cfi_def_cfa_offset 16"),
+        "pop ecx",
+        cfi!(".cfi_def_cfa_offset 12"),
         // move the length of the stack
-        "mov edi, [{stack_start}]", // store the start of the stack to edi
+        "mov edi, [eax]", // store the start of the stack to edi
         "sub edi, esp", // then store the length (start - end)
 
         "push ecx", // push the 3º argument of f
+        cfi!(".cfi_def_cfa_offset 16"),
         "push edi", // push the 2º argument of f
+        cfi!(".cfi_def_cfa_offset 20"),
         "push esi", // push the 1º argument of f
+        cfi!(".cfi_def_cfa_offset 24"),
         "call edx", // call f
+        cfi!(".cfi_def_cfa_offset 12"), // f popped its arguments
         // if we reach here that means f returned and we must restore everything to as it was
         // pop callee saved registers
         "pop edi",
+        cfi!(".cfi_def_cfa_offset 8", ".cfi_restore edi"),
         "pop esi",
+        cfi!(".cfi_def_cfa_offset 4", ".cfi_restore esi"),
         "pop ebx",
+        cfi!(".cfi_def_cfa_offset 0", ".cfi_restore ebx"),
         "pop ebp",
+        cfi!(".cfi_escape 0x13, 0x01", ".cfi_restore ebp"), // a cfa offset of -4
         // return (read and jump to the return address from the freshely copied stack)
         "ret",
-        stack_start = sym STACK_START
+        cfi!(".cfi_endproc"),
+        stack_start = sym stack_start,
     )
 }
 
 /// ### the purpose of this function:
 ///
-/// it "lands" a previously saved stack onto the dock, overwriting the current execution context and resuming the saved one.
+/// it resumes the stack that was suspended last, when its bytes are still on the dock because nothing landed or restarted since then.
 ///
-/// this is the core mechanism for switching to a suspended coroutine. because it completely replaces the current stack, this function never returns.
+/// this is `resume` without the copy and without the callback, so a yield that comes right back costs close to nothing.
 ///
 /// ### what this function does:
 ///
-/// * it reads its arguments (`stack_data`, `stack_len`, etc.) from the stack and stores them in registers, as the stack is about to be overwritten.
-/// * it calculates the new stack pointer by subtracting the `stack_len` from the `STACK_START` address.
-/// * it sets the machine's stack pointer (`esp`) to this new address. the new stack is now live, though its contents are still undefined.
-/// * using `rep movsb`, it performs a fast, non-stack-based memory copy, populating the new stack with the bytes from `stack_data`.
-/// * it calls the post-copy callback `f`, giving the caller a chance to free the buffer that held the saved stack data.
-/// * after the callback returns, it begins popping values from the newly restored stack. it first restores the callee-saved registers (`edi`, `esi`, `ebx`, `ebp`).
-/// * finally, it executes a `ret`, which pops the return address from the top of the new stack and jumps to it, seamlessly resuming the suspended code.
+/// * it reads `STACK_START` and sets `esp` to `STACK_START - stack_len`, which is where `suspend` left the stack.
+/// * it pops the callee-saved registers (`edi`, `esi`, `ebx`, `ebp`) pushed by `suspend`, and executes a `ret` to the return address pushed before them.
 ///
 /// ### Safety
 ///
-/// this function is extremely unsafe because it overwrites the current stack by moving the stack pointer directly. **It does not run any destructors** for objects that go out of scope. Any RAII guards (like `Box`, `Vec`, file handles, etc.) on the abandoned stack will be leaked. It must only be called when it is certain that no pending destructors need to be run.
+/// the same as `resume`, on top of that the bytes on the dock must be exactly as `suspend` left them.
 #[unsafe(naked)]
-pub(crate) unsafe extern "stdcall" fn resume<A>(
-    stack_data: *const u8,
-    stack_len: usize,
-    a: *mut A,
-    f: unsafe extern "stdcall" fn(*const u8, usize, *mut A),
-) -> ! {
+pub(crate) unsafe extern "stdcall" fn resume_in_place(stack_len: usize) -> ! {
     naked_asm!(
-        // remove things from the stack so we can trash it
-        "add esp, 4", // pop the return address
-        "pop esi",    // pop the stack_data
-        "pop ebx",    // pop the stack_len
-        "pop edx",    // pop the argument
-        "pop eax",    // pop the function (yes we use the stack base pointer register, we are very short on register when the stack is out of commission)
-
-        // copy over the bytes and set esp (must not use the stack, memcpy would not work here because of that)
-        "mov ecx, ebx", // the amount of bytes to copy (ecx) is the stack_len (ebx)
-        // "mov esi, esi", // the start address of the source (esi) is stack_data (esi)
-        "mov edi, [{stack_start}]", // the start address of the destination (edi) is stack_start...
-        "sub edi, ebx", // ...minus the number of bytes of the new stack
-        "mov esp, edi", // the new stack pointer is stack_start - the length of the stack
-        "cld", // clear the direction flag
-        "rep movsb", // copy ecx bytes from [esi] to [edi]
-
-        "sub esi, ebx", // restore the stack_data back to its original value for f
-
-        // call f
-        "push edx", // 3º arg: a
-        "push ebx", // 2º arg: stack_len
-        "push esi", // 1º arg: stack_data
-        "call eax",
-
-        // pop callee saved registers (from the freshly copied stack)
+        cfi!(".cfi_startproc"),
+        "call {stack_start}", // get the address of STACK_START while the stack is still usable
+        "mov edi, [eax]",     // the stack starts at stack_start...
+        "sub edi, [esp+4]",   // ...minus the stack_len
+        "mov esp, edi",       // and the bytes are already there
+        // the same frame `suspend` had right before calling its callback
+        cfi!(".cfi_def_cfa_offset 12", ".cfi_offset eip, 4", ".cfi_offset ebp, 0", ".cfi_offset ebx, -4", ".cfi_offset esi, -8", ".cfi_offset edi, -12"),
+        // pop callee saved registers (left there by suspend)
         "pop edi",
+        cfi!(".cfi_def_cfa_offset 8", ".cfi_restore edi"),
         "pop esi",
+        cfi!(".cfi_def_cfa_offset 4", ".cfi_restore esi"),
         "pop ebx",
+        cfi!(".cfi_def_cfa_offset 0", ".cfi_restore ebx"),
         "pop ebp",
-        // return (read and jump to the return address from the freshely copied stack)
+        cfi!(".cfi_escape 0x13, 0x01", ".cfi_restore ebp"), // a cfa offset of -4
+        // return (read and jump to the return address left there by suspend)
         "ret",
-        stack_start = sym STACK_START
+        cfi!(".cfi_endproc"),
+        stack_start = sym stack_start,
     )
 }
+
+/// generates a function like `resume`, with the given instructions for copying the stack
+///
+/// the copy gets `ecx` = `stack_len` (always a multiple of 4 for stacks taken by `suspend`), `esi` = `stack_data` and `edi` = the new `esp`,
+/// it must leave `esi` one past the end of the source, and it must not use the stack, nor the registers `eax`, `ebx` and `edx`
+macro_rules! resume_with_copy {
+    ($(#[$attr:meta])* $vis:vis fn $name:ident { $($copy:literal,)* }) => {
+        $(#[$attr])*
+        #[unsafe(naked)]
+        $vis unsafe extern "stdcall" fn $name<A>(
+            stack_data: *const u8,
+            stack_len: usize,
+            a: *mut A,
+            f: unsafe extern "stdcall" fn(*const u8, usize, *mut A),
+        ) -> ! {
+            naked_asm!(
+                cfi!(".cfi_startproc"),
+                // get the address of STACK_START while the stack is still usable
+                "call {stack_start}",
+                "mov edi, [eax]", // the start address of the destination (edi) is stack_start...
+
+                // remove things from the stack so we can trash it
+                "add esp, 4", // pop the return address
+                cfi!(".cfi_undefined eip"), // there is nothing to unwind to until the new stack is copied
+                "pop esi",    // pop the stack_data
+                "pop ebx",    // pop the stack_len
+                "pop edx",    // pop the argument
+                "pop eax",    // pop the function (yes we use the stack base pointer register, we are very short on register when the stack is out of commission)
+
+                // copy over the bytes and set esp (must not use the stack, memcpy would not work here because of that)
+                "mov ecx, ebx", // the amount of bytes to copy (ecx) is the stack_len (ebx)
+                // "mov esi, esi", // the start address of the source (esi) is stack_data (esi)
+                "sub edi, ebx", // ...minus the number of bytes of the new stack
+                "mov esp, edi", // the new stack pointer is stack_start - the length of the stack
+                $($copy,)*
+
+                "sub esi, ebx", // restore the stack_data back to its original value for f
+
+                // the same frame `suspend` had right before calling its callback
+                cfi!(".cfi_def_cfa_offset 12", ".cfi_offset eip, 4", ".cfi_offset ebp, 0", ".cfi_offset ebx, -4", ".cfi_offset esi, -8", ".cfi_offset edi, -12"),
+
+                // call f
+                "push edx", // 3º arg: a
+                cfi!(".cfi_def_cfa_offset 16"),
+                "push ebx", // 2º arg: stack_len
+                cfi!(".cfi_def_cfa_offset 20"),
+                "push esi", // 1º arg: stack_data
+                cfi!(".cfi_def_cfa_offset 24"),
+                "call eax",
+                cfi!(".cfi_def_cfa_offset 12"), // f popped its arguments
+
+                // pop callee saved registers (from the freshly copied stack)
+                "pop edi",
+                cfi!(".cfi_def_cfa_offset 8", ".cfi_restore edi"),
+                "pop esi",
+                cfi!(".cfi_def_cfa_offset 4", ".cfi_restore esi"),
+                "pop ebx",
+                cfi!(".cfi_def_cfa_offset 0", ".cfi_restore ebx"),
+                "pop ebp",
+                cfi!(".cfi_escape 0x13, 0x01", ".cfi_restore ebp"), // a cfa offset of -4
+                // return (read and jump to the return address from the freshely copied stack)
+                "ret",
+                cfi!(".cfi_endproc"),
+                stack_start = sym stack_start,
+            )
+        }
+    };
+}
+
+resume_with_copy! {
+    /// ### the purpose of this function:
+    ///
+    /// it "lands" a previously saved stack onto the dock, overwriting the current execution context and resuming the saved one.
+    ///
+    /// this is the core mechanism for switching to a suspended coroutine. because it completely replaces the current stack, this function never returns.
+    ///
+    /// ### what this function does:
+    ///
+    /// * it reads its arguments (`stack_data`, `stack_len`, etc.) from the stack and stores them in registers, as the stack is about to be overwritten.
+    /// * it reads `STACK_START` while its stack is still usable, then calculates the new stack pointer by subtracting the `stack_len` from it.
+    /// * it sets the machine's stack pointer (`esp`) to this new address. the new stack is now live, though its contents are still undefined.
+    /// * it populates the new stack with the bytes from `stack_data`, with a copy loop that does not use the stack: 64 bytes at a time through the sse registers, then `rep movsd` for the remaining dwords and `rep movsb` for the remaining bytes (if sse2 is disabled only the last two are used).
+    /// * it calls the post-copy callback `f`, giving the caller a chance to free the buffer that held the saved stack data.
+    /// * after the callback returns, it begins popping values from the newly restored stack. it first restores the callee-saved registers (`edi`, `esi`, `ebx`, `ebp`).
+    /// * finally, it executes a `ret`, which pops the return address from the top of the new stack and jumps to it, seamlessly resuming the suspended code.
+    ///
+    /// ### Safety
+    ///
+    /// this function is extremely unsafe because it overwrites the current stack by moving the stack pointer directly. **It does not run any destructors** for objects that go out of scope. Any RAII guards (like `Box`, `Vec`, file handles, etc.) on the abandoned stack will be leaked. It must only be called when it is certain that no pending destructors need to be run.
+    #[cfg(target_feature = "sse2")]
+    pub(crate) fn resume {
+        "shr ecx, 6", // the number of 64 byte blocks
+        "jz 3f",
+        "2:",
+        "movdqu xmm0, [esi]",
+        "movdqu xmm1, [esi+16]",
+        "movdqu xmm2, [esi+32]",
+        "movdqu xmm3, [esi+48]",
+        "movdqu [edi], xmm0",
+        "movdqu [edi+16], xmm1",
+        "movdqu [edi+32], xmm2",
+        "movdqu [edi+48], xmm3",
+        "add esi, 64",
+        "add edi, 64",
+        "dec ecx",
+        "jnz 2b",
+        "3:",
+        "mov ecx, ebx",
+        "and ecx, 63", // the bytes that did not fill a block...
+        "shr ecx, 2", // ...as dwords
+        "cld", // clear the direction flag
+        "rep movsd", // copy ecx dwords from [esi] to [edi]
+        "mov ecx, ebx",
+        "and ecx, 3", // the bytes that did not fill a dword
+        "rep movsb", // copy ecx bytes from [esi] to [edi]
+    }
+}
+
+resume_with_copy! {
+    #[cfg(not(target_feature = "sse2"))]
+    pub(crate) fn resume {
+        "shr ecx, 2", // the number of dwords
+        "cld", // clear the direction flag
+        "rep movsd", // copy ecx dwords from [esi] to [edi]
+        "mov ecx, ebx",
+        "and ecx, 3", // the bytes that did not fill a dword
+        "rep movsb", // copy ecx bytes from [esi] to [edi]
+    }
+}
+
+resume_with_copy! {
+    /// the original `resume`, which copies one byte at a time, kept around to compare against
+    #[cfg(test)]
+    pub(crate) fn resume_bytewise {
+        "cld", // clear the direction flag
+        "rep movsb", // copy ecx bytes from [esi] to [edi]
+    }
+}
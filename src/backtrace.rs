@@ -0,0 +1,132 @@
+//! symbolized backtraces of suspended stacks, through dbghelp
+
+use std::ffi::CStr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::{Stack, sys};
+
+/// the backtrace of a suspended stack, see [`Stack::backtrace`]
+///
+/// displays like the backtrace of a panic
+#[derive(Clone, Debug, Default)]
+pub struct Backtrace {
+    pub frames: Vec<BacktraceFrame>,
+}
+
+/// a frame of a [`Backtrace`], the function, file and line are `None` if dbghelp could not find them
+#[derive(Clone, Debug)]
+pub struct BacktraceFrame {
+    /// the return address of the frame, as it is once the stack lands
+    pub address: *const u8,
+    pub function: Option<String>,
+    pub file: Option<PathBuf>,
+    pub line: Option<u32>,
+}
+
+/// dbghelp is not thread safe, every call of this crate goes through this lock, which also knows if it was initialized
+///
+/// the lock only covers this crate, std also uses dbghelp (to print the backtrace of a panic) and so may other libraries, without taking it
+static DBGHELP: Mutex<bool> = Mutex::new(false);
+
+/// the longest symbol name dbghelp gives back
+const MAX_NAME_LEN: usize = 1024;
+
+#[repr(C)]
+struct SymbolBuffer {
+    info: sys::SYMBOL_INFO,
+    name: [u8; MAX_NAME_LEN],
+}
+
+fn symbolize(address: *const u8) -> BacktraceFrame {
+    let mut frame = BacktraceFrame {
+        address,
+        function: None,
+        file: None,
+        line: None,
+    };
+    let mut initialized = DBGHELP
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    unsafe {
+        let process = sys::GetCurrentProcess();
+        if !*initialized {
+            sys::SymSetOptions(
+                sys::SYMOPT_UNDNAME | sys::SYMOPT_DEFERRED_LOADS | sys::SYMOPT_LOAD_LINES,
+            );
+            *initialized = if sys::SymInitialize(process, std::ptr::null(), 1) != 0 {
+                true
+            } else if std::io::Error::last_os_error().raw_os_error()
+                == Some(sys::ERROR_INVALID_PARAMETER)
+            {
+                // std or some other library initialized it first, which is just as good, but they may have done it before some modules were loaded
+                sys::SymRefreshModuleList(process);
+                true
+            } else {
+                false
+            };
+            if !*initialized {
+                return frame;
+            }
+        }
+        // a return address is right after the call, the instruction before it is the one that is waiting
+        let lookup = (address as u64).saturating_sub(1);
+
+        let mut symbol: SymbolBuffer = std::mem::zeroed();
+        symbol.info.SizeOfStruct = size_of::<sys::SYMBOL_INFO>() as u32;
+        symbol.info.MaxNameLen = MAX_NAME_LEN as u32;
+        let mut displacement = 0u64;
+        if sys::SymFromAddr(process, lookup, &mut displacement, &mut symbol.info) != 0 {
+            let name = std::slice::from_raw_parts(
+                symbol.info.Name.as_ptr() as *const u8,
+                (symbol.info.NameLen as usize).min(MAX_NAME_LEN),
+            );
+            frame.function = Some(String::from_utf8_lossy(name).into_owned());
+        }
+
+        let mut line: sys::IMAGEHLP_LINE64 = std::mem::zeroed();
+        line.SizeOfStruct = size_of::<sys::IMAGEHLP_LINE64>() as u32;
+        let mut displacement = 0u32;
+        if sys::SymGetLineFromAddr64(process, lookup, &mut displacement, &mut line) != 0 {
+            frame.line = Some(line.LineNumber);
+            if !line.FileName.is_null() {
+                let file = CStr::from_ptr(line.FileName).to_string_lossy().into_owned();
+                frame.file = Some(PathBuf::from(file));
+            }
+        }
+    }
+    frame
+}
+
+impl Stack {
+    /// the frames of the stack (see [`Stack::frames`]) with the function, file and line of their return addresses
+    ///
+    /// the symbols come from the pdb of each module, frames without one only have their address
+    ///
+    /// dbghelp is single threaded, this must not run while another thread uses it, which includes std capturing the backtrace of a panic,
+    /// the calls of this crate are serialized with each other, but nothing serializes them with the calls of std or other libraries
+    pub fn backtrace(&self) -> Backtrace {
+        Backtrace {
+            frames: self
+                .frames()
+                .map(|frame| symbolize(frame.return_address))
+                .collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, frame) in self.frames.iter().enumerate() {
+            write!(f, "{index:>4}: ")?;
+            match &frame.function {
+                Some(function) => writeln!(f, "{function}")?,
+                None => writeln!(f, "{:?}", frame.address)?,
+            }
+            if let (Some(file), Some(line)) = (&frame.file, frame.line) {
+                writeln!(f, "             at {}:{line}", file.display())?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+//! a limit on the memory held by the suspended stacks of a dock, and what to do once a suspension would go past it
+//!
+//! see [`Stack::set_memory_budget`](crate::Stack::set_memory_budget)
+
+use std::rc::Rc;
+
+use crate::{Codec, SpillFile, Stack, dock};
+
+/// what to do with a stack whose suspension would go past the memory budget of its dock, returned by the policy passed to [`Stack::set_memory_budget`]
+pub enum Pressure {
+    /// do not suspend, the stack keeps running as if it was resumed right away, see [`Stack::try_suspend`]
+    Reject,
+    /// compress the stack, see [`Stack::compress`]
+    Compress(&'static dyn Codec),
+    /// spill the stack to the file, see [`Stack::spill`]
+    Spill(Rc<SpillFile>),
+    /// suspend the stack as usual, but block whoever spawns coroutines on the dock (like [`Executor::spawn`](crate::Executor::spawn)) until the suspended stacks fit in the budget again
+    Block,
+}
+
+impl std::fmt::Debug for Pressure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pressure::Reject => f.write_str("Reject"),
+            Pressure::Compress(_) => f.write_str("Compress"),
+            Pressure::Spill(file) => f.debug_tuple("Spill").field(file).finish(),
+            Pressure::Block => f.write_str("Block"),
+        }
+    }
+}
+
+/// the error of [`Stack::try_suspend`] when the policy of the memory budget returned [`Pressure::Reject`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BudgetExceeded;
+
+impl std::fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the suspension would go past the memory budget of the dock")
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+pub(crate) type Policy = Box<dyn FnMut(&mut Stack) -> Pressure>;
+
+pub(crate) struct Budget {
+    pub(crate) limit: usize,
+    /// taken out of the dock while it is called, so that it can use the dock
+    pub(crate) policy: Option<Policy>,
+}
+
+/// calls the policy of the budget of the current dock if the stack that is being suspended would not fit in it
+pub(crate) fn apply(stack: &mut Stack) -> Result<(), BudgetExceeded> {
+    let policy = dock::try_with(|dock| {
+        let budget = dock.budget.as_mut()?;
+        // the stack may already be counted, if its bytes were copied out
+        if dock.suspended_bytes.saturating_sub(stack.accounted) + stack.len() <= budget.limit {
+            return None;
+        }
+        budget.policy.take()
+    })
+    .flatten();
+    let Some(mut policy) = policy else {
+        return Ok(());
+    };
+    let pressure = policy(stack);
+    dock::with(|dock| {
+        // unless the policy replaced the budget
+        if let Some(budget) = &mut dock.budget
+            && budget.policy.is_none()
+        {
+            budget.policy = Some(policy);
+        }
+    });
+    match pressure {
+        Pressure::Reject => return Err(BudgetExceeded),
+        Pressure::Compress(codec) => {
+            stack.compress(codec);
+        }
+        Pressure::Spill(file) => {
+            // if the file cannot be written to, the stack just stays in memory
+            let _ = stack.spill(&file);
+        }
+        Pressure::Block => dock::with(|dock| dock.shared.block()),
+    }
+    Ok(())
+}
@@ -0,0 +1,86 @@
+//! content addressed chunks of suspended stacks, so that the frames many coroutines have in common are stored once
+//!
+//! see [`Stack::set_deduplication`](crate::Stack::set_deduplication)
+
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::rc::{Rc, Weak};
+
+/// the size of a chunk, every stack is split from its start (the dock) so that the bottom frames line up across coroutines
+pub(crate) const CHUNK_SIZE: usize = 512;
+
+/// the chunks of the stacks suspended in a dock, by the hash of their bytes
+#[derive(Default)]
+pub(crate) struct ChunkStore {
+    chunks: HashMap<u64, Vec<Weak<[u8]>>>,
+    /// the number of weak references in `chunks`, some of which may be dead
+    entries: usize,
+    /// when `entries` gets past this the dead references are removed
+    sweep_at: usize,
+    stats: ChunkStats,
+}
+
+/// statistics of the deduplication of a dock, see [`Stack::chunk_stats`](crate::Stack::chunk_stats)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChunkStats {
+    /// bytes that had to be stored in a new chunk
+    pub stored_bytes: u64,
+    /// bytes that were found in a chunk that was already stored, and did not take any memory
+    pub shared_bytes: u64,
+}
+
+impl ChunkStore {
+    /// splits the bytes of a stack into chunks, from the end of `bytes` (the start of the stack) to its beginning
+    pub(crate) fn split(&mut self, bytes: &[u8]) -> Vec<Rc<[u8]>> {
+        bytes
+            .rchunks(CHUNK_SIZE)
+            .map(|chunk| self.intern(chunk))
+            .collect()
+    }
+
+    /// appends the bytes of the chunks returned by [`ChunkStore::split`] to `out`
+    pub(crate) fn join(chunks: &[Rc<[u8]>], out: &mut Vec<u8>) {
+        for chunk in chunks.iter().rev() {
+            out.extend_from_slice(chunk);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> ChunkStats {
+        self.stats
+    }
+
+    fn intern(&mut self, bytes: &[u8]) -> Rc<[u8]> {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        let bucket = self.chunks.entry(hasher.finish()).or_default();
+        let before = bucket.len();
+        bucket.retain(|chunk| chunk.strong_count() != 0);
+        self.entries -= before - bucket.len();
+        if let Some(chunk) = bucket
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|chunk| **chunk == *bytes)
+        {
+            self.stats.shared_bytes += bytes.len() as u64;
+            return chunk;
+        }
+        let chunk: Rc<[u8]> = Rc::from(bytes);
+        bucket.push(Rc::downgrade(&chunk));
+        self.stats.stored_bytes += bytes.len() as u64;
+        self.entries += 1;
+        if self.entries > self.sweep_at {
+            self.sweep();
+        }
+        chunk
+    }
+
+    /// removes the references to chunks that were dropped
+    fn sweep(&mut self) {
+        self.chunks.retain(|_, bucket| {
+            bucket.retain(|chunk| chunk.strong_count() != 0);
+            !bucket.is_empty()
+        });
+        self.entries = self.chunks.values().map(Vec::len).sum();
+        self.sweep_at = (self.entries * 2).max(1024);
+    }
+}
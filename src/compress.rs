@@ -0,0 +1,98 @@
+//! compression of the bytes of suspended stacks, for docks that keep a lot of idle coroutines around
+//!
+//! see [`Stack::compress`](crate::Stack::compress) and [`Stack::set_compression`](crate::Stack::set_compression)
+
+/// a way to compress the bytes of a suspended stack
+///
+/// `decompress` must append exactly the bytes that were given to `compress`
+pub trait Codec {
+    /// appends the compressed form of `bytes` to `out`
+    fn compress(&self, bytes: &[u8], out: &mut Vec<u8>);
+    /// appends the bytes that were compressed into `bytes` to `out`
+    fn decompress(&self, bytes: &[u8], out: &mut Vec<u8>);
+}
+
+/// the built-in codec, a small lz77
+///
+/// suspended stacks are mostly zero padding and frames that repeat, both of which end up as back references
+///
+/// the compressed bytes are a list of tokens:
+/// - `0nnnnnnn` followed by `n + 1` bytes to copy as they are
+/// - `1nnnnnnn` followed by a little endian `u16` offset, copies `n + 4` bytes starting `offset` bytes back (they can overlap what is being copied)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Lz;
+
+/// the shortest back reference worth encoding, it takes 3 bytes
+const MIN_MATCH: usize = 4;
+const MAX_MATCH: usize = MIN_MATCH + 0x7f;
+const MAX_LITERALS: usize = 0x80;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+
+fn hash(bytes: &[u8]) -> usize {
+    let word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (word.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn push_literals(mut literals: &[u8], out: &mut Vec<u8>) {
+    while !literals.is_empty() {
+        let (now, rest) = literals.split_at(literals.len().min(MAX_LITERALS));
+        out.push((now.len() - 1) as u8);
+        out.extend_from_slice(now);
+        literals = rest;
+    }
+}
+
+impl Codec for Lz {
+    fn compress(&self, bytes: &[u8], out: &mut Vec<u8>) {
+        // the last position each hash was seen at, plus one so that zero means never
+        let mut table = vec![0usize; 1 << HASH_BITS];
+        let mut literals = 0;
+        let mut i = 0;
+        while i + MIN_MATCH <= bytes.len() {
+            let slot = &mut table[hash(&bytes[i..])];
+            let candidate = std::mem::replace(slot, i + 1).wrapping_sub(1);
+            if candidate == usize::MAX
+                || i - candidate > MAX_OFFSET
+                || bytes[candidate..candidate + MIN_MATCH] != bytes[i..i + MIN_MATCH]
+            {
+                i += 1;
+                continue;
+            }
+            let mut len = MIN_MATCH;
+            while i + len < bytes.len()
+                && len < MAX_MATCH
+                && bytes[candidate + len] == bytes[i + len]
+            {
+                len += 1;
+            }
+            push_literals(&bytes[literals..i], out);
+            out.push(0x80 | (len - MIN_MATCH) as u8);
+            out.extend_from_slice(&((i - candidate) as u16).to_le_bytes());
+            i += len;
+            literals = i;
+        }
+        push_literals(&bytes[literals..], out);
+    }
+
+    fn decompress(&self, bytes: &[u8], out: &mut Vec<u8>) {
+        let mut i = 0;
+        while i < bytes.len() {
+            let token = bytes[i] as usize;
+            i += 1;
+            if token & 0x80 == 0 {
+                out.extend_from_slice(&bytes[i..i + token + 1]);
+                i += token + 1;
+            } else {
+                let len = (token & 0x7f) + MIN_MATCH;
+                let offset = u16::from_le_bytes([bytes[i], bytes[i + 1]]) as usize;
+                i += 2;
+                let start = out.len() - offset;
+                // byte by byte, the source can overlap the bytes being written
+                for index in start..start + len {
+                    out.push(out[index]);
+                }
+            }
+        }
+    }
+}
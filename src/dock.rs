@@ -0,0 +1,516 @@
+//! the state of a dock, this is what lets code running inside of [`Stack::dock`] find the run queue of the dock it is running on
+//!
+//! every call to [`Stack::dock`] installs a fresh [`Dock`] in a thread local and removes it once the dock returns
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::panic::Location;
+use std::rc::{Rc, Weak};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::Waker;
+
+use crate::asm;
+use crate::budget::Budget;
+use crate::chunk::ChunkStore;
+use crate::compress::Codec;
+use crate::live::LiveStack;
+use crate::poison::Scanner;
+use crate::pool::Pool;
+use crate::region::Region;
+use crate::registry::{self, Registry};
+use crate::stats::DockStats;
+use crate::watch::WriteWatch;
+use crate::{SpillFile, Stack, asan, sys, trace};
+
+pub(crate) struct Dock {
+    /// stacks that are ready to be resumed, in order
+    pub(crate) run_queue: VecDeque<Stack>,
+    /// stacks waiting for a call to [`WakeHandle::wake`], by the id of their handle, which also makes them oldest first
+    pub(crate) parked: BTreeMap<u64, Stack>,
+    /// the bytes of the parked stacks that are in memory, see [`Stack::resident_len`]
+    parked_resident: usize,
+    pub(crate) shared: Arc<Shared>,
+    /// what to do when every stack is parked
+    pub(crate) idle: Idle,
+    /// whether the dock saw [`Shared::close`] being called
+    closed: bool,
+    /// recycles the buffers of the stacks that land on this dock
+    pub(crate) pool: Pool,
+    /// stacks of up to this many bytes are stored inline, see [`Stack::set_inline_threshold`]
+    pub(crate) inline_threshold: usize,
+    /// set while docked on a region with a write watch, see [`Region::with_write_watch`]
+    pub(crate) watch: Option<WriteWatch>,
+    /// set while suspended stacks are deduplicated, see [`Stack::set_deduplication`]
+    pub(crate) chunks: Option<ChunkStore>,
+    /// the bytes held by the stacks suspended in this dock, see [`Stack::suspended_bytes`]
+    pub(crate) suspended_bytes: usize,
+    /// set by [`Stack::set_memory_budget`]
+    pub(crate) budget: Option<Budget>,
+    /// the codec parked stacks of at least that many bytes are compressed with, see [`Stack::set_compression`]
+    pub(crate) compression: Option<(&'static dyn Codec, usize)>,
+    /// the file parked stacks are spilled to once they take more than that many bytes, see [`Stack::set_spill_budget`]
+    pub(crate) spill: Option<(Rc<SpillFile>, usize)>,
+    /// every parked stack with a smaller id was already spilled or does not take any memory
+    spill_cursor: u64,
+    /// the coroutines of the dock, see [`Stack::coroutines`]
+    pub(crate) registry: Registry,
+    /// see [`Stack::dock_stats`]
+    pub(crate) stats: DockStats,
+    /// set by [`Stack::set_poison`]
+    pub(crate) poison: bool,
+    /// the objects registered with [`Stack::scan_for_escapes`]
+    pub(crate) scanner: Scanner,
+    /// set by [`Stack::set_resume_in_place`]
+    pub(crate) resume_in_place: bool,
+    /// the stack suspended last, if its bytes are still on the dock
+    pub(crate) live: Weak<LiveStack>,
+    next_id: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Idle {
+    /// block the thread until a parked stack is woken
+    Wait,
+    /// leave the dock by restarting with `()`, this is how a [`CoroutineFuture`](crate::CoroutineFuture) returns [`Poll::Pending`](std::task::Poll::Pending)
+    Leave,
+    /// block the thread until a parked stack is woken or a new one is spawned, even if nothing is parked, until [`Shared::close`] is called
+    ///
+    /// this is how the workers of an [`Executor`](crate::Executor) wait for work
+    Serve,
+}
+
+/// the part of the dock that other threads are allowed to touch
+pub(crate) struct Shared {
+    pending: Mutex<Pending>,
+    /// notified whenever something is pushed to `pending`
+    idle: Condvar,
+    /// set while spawning to the dock should wait, see [`Pressure::Block`](crate::Pressure::Block)
+    blocked: Mutex<bool>,
+    /// notified when `blocked` is cleared
+    unblocked: Condvar,
+    /// woken together with `idle`, for docks that are driven by an async executor
+    waker: Mutex<Option<Waker>>,
+}
+
+type SpawnedEntry = Box<dyn FnOnce() + Send>;
+
+/// what other threads left for the dock since the last time it looked
+#[derive(Default)]
+struct Pending {
+    /// the ids of the handles that were woken
+    woken: Vec<u64>,
+    /// entry functions to turn into new stacks, they must never return, with where they were spawned from
+    spawned: Vec<(SpawnedEntry, &'static Location<'static>)>,
+    closed: bool,
+}
+
+thread_local! {
+    static DOCK: RefCell<Option<Dock>> = const { RefCell::new(None) };
+    static DEBUG_LINK: DebugLink = DebugLink::new();
+}
+
+/// the dock of a thread, for debuggers, see `gdb/stack_master.py`
+///
+/// a debugger can not reach the thread locals of the threads on its own, so every thread that docks links one of these into [`DEBUG_DOCKS`],
+/// they are kept in plain pointers so that the list can be walked without knowing how std lays out its types
+#[repr(C)]
+struct DebugDock {
+    next: *mut DebugDock,
+    /// as returned by `GetCurrentThreadId`
+    thread: u32,
+    /// the thread local the dock of the thread is installed in, it holds `None` while the thread is not docked
+    dock: *const RefCell<Option<Dock>>,
+}
+
+/// the head of the list of every [`DebugDock`], only changed while [`DEBUG_DOCKS_LOCK`] is held
+#[used]
+static mut DEBUG_DOCKS: *mut DebugDock = std::ptr::null_mut();
+static DEBUG_DOCKS_LOCK: Mutex<()> = Mutex::new(());
+
+/// keeps the [`DebugDock`] of the thread linked until the thread exits
+struct DebugLink(*mut DebugDock);
+
+impl DebugLink {
+    fn new() -> Self {
+        let link = Box::into_raw(Box::new(DebugDock {
+            next: std::ptr::null_mut(),
+            thread: unsafe { sys::GetCurrentThreadId() },
+            dock: DOCK.with(|dock| dock as *const _),
+        }));
+        let _lock = DEBUG_DOCKS_LOCK.lock().unwrap();
+        unsafe {
+            (*link).next = DEBUG_DOCKS;
+            DEBUG_DOCKS = link;
+        }
+        DebugLink(link)
+    }
+}
+
+impl Drop for DebugLink {
+    fn drop(&mut self) {
+        let _lock = DEBUG_DOCKS_LOCK.lock().unwrap();
+        unsafe {
+            let mut next = &raw mut DEBUG_DOCKS;
+            while *next != self.0 {
+                next = &raw mut (**next).next;
+            }
+            *next = (*self.0).next;
+            drop(Box::from_raw(self.0));
+        }
+    }
+}
+
+/// a handle that can make a parked [`Stack`] runnable again, from any thread
+///
+/// see [`Stack::park`] for how to get one
+#[derive(Clone)]
+pub struct WakeHandle {
+    shared: Arc<Shared>,
+    id: u64,
+}
+
+#[allow(clippy::large_enum_variant)]
+enum Next {
+    Run(Stack),
+    Wait(Arc<Shared>, bool),
+    Leave,
+    Empty,
+}
+
+impl Dock {
+    pub(crate) fn new() -> Self {
+        Self::with_shared(Arc::new(Shared::new()))
+    }
+
+    pub(crate) fn with_shared(shared: Arc<Shared>) -> Self {
+        Dock {
+            run_queue: VecDeque::new(),
+            parked: BTreeMap::new(),
+            parked_resident: 0,
+            shared,
+            idle: Idle::Wait,
+            closed: false,
+            pool: Pool::default(),
+            inline_threshold: crate::INLINE_CAPACITY,
+            watch: None,
+            chunks: None,
+            suspended_bytes: 0,
+            budget: None,
+            compression: None,
+            spill: None,
+            spill_cursor: 0,
+            registry: Registry::default(),
+            poison: false,
+            stats: DockStats::default(),
+            scanner: Scanner::default(),
+            resume_in_place: false,
+            live: Weak::new(),
+            next_id: 0,
+        }
+    }
+
+    /// creates a handle with a new id, nothing is parked under it yet
+    pub(crate) fn handle(&mut self) -> WakeHandle {
+        self.next_id += 1;
+        WakeHandle {
+            shared: self.shared.clone(),
+            id: self.next_id,
+        }
+    }
+
+    /// parks the stack until the handle is woken
+    pub(crate) fn park(&mut self, handle: &WakeHandle, stack: Stack) {
+        debug_assert!(Arc::ptr_eq(&self.shared, &handle.shared));
+        self.parked_resident += stack.resident_len();
+        self.parked.insert(handle.id, stack);
+    }
+
+    /// a stack went from holding `old` bytes to holding `new` bytes
+    pub(crate) fn account(&mut self, old: usize, new: usize) {
+        self.suspended_bytes = (self.suspended_bytes + new).saturating_sub(old);
+        if let Some(budget) = &self.budget
+            && self.suspended_bytes <= budget.limit
+        {
+            self.shared.unblock();
+        }
+    }
+
+    /// moves the stacks of every handle that was woken and every spawned entry into the run queue
+    fn drain_pending(&mut self) {
+        let pending = std::mem::take(&mut *self.shared.pending.lock().unwrap());
+        for id in pending.woken {
+            // handles may be woken more than once, or while their stack is not parked
+            if let Some(stack) = self.parked.remove(&id) {
+                self.parked_resident -= stack.resident_len();
+                self.run_queue.push_back(stack);
+            }
+        }
+        for (entry, location) in pending.spawned {
+            let id = registry::new_id();
+            self.registry.created(id, None, location);
+            self.run_queue
+                .push_back(unsafe { Stack::from_entry_with_id(entry, Some(id)) });
+        }
+        self.closed |= pending.closed;
+    }
+}
+
+impl Drop for Dock {
+    fn drop(&mut self) {
+        // the suspended bytes are gone with the dock, the stacks must not take theirs off whatever dock is current when they are dropped
+        for stack in self.run_queue.iter_mut().chain(self.parked.values_mut()) {
+            stack.forget_accounted();
+        }
+    }
+}
+
+impl Shared {
+    pub(crate) fn new() -> Self {
+        Shared {
+            pending: Mutex::new(Pending::default()),
+            idle: Condvar::new(),
+            blocked: Mutex::new(false),
+            unblocked: Condvar::new(),
+            waker: Mutex::new(None),
+        }
+    }
+
+    /// sends an entry function to the dock, which will run it in a new stack
+    ///
+    /// the entry function must never return, it must leave through something like [`Stack::run_next`] instead
+    pub(crate) fn spawn(&self, entry: SpawnedEntry, location: &'static Location<'static>) {
+        self.pending.lock().unwrap().spawned.push((entry, location));
+        self.notify();
+    }
+
+    /// lets an [`Idle::Serve`] dock leave once it has nothing left to run
+    pub(crate) fn close(&self) {
+        self.pending.lock().unwrap().closed = true;
+        self.notify();
+    }
+
+    fn notify(&self) {
+        self.idle.notify_one();
+        if let Some(waker) = &*self.waker.lock().unwrap() {
+            waker.wake_by_ref();
+        }
+    }
+
+    /// makes spawners wait until [`Shared::unblock`] is called
+    pub(crate) fn block(&self) {
+        *self.blocked.lock().unwrap() = true;
+    }
+
+    pub(crate) fn unblock(&self) {
+        let mut blocked = self.blocked.lock().unwrap();
+        if *blocked {
+            *blocked = false;
+            self.unblocked.notify_all();
+        }
+    }
+
+    pub(crate) fn is_blocked(&self) -> bool {
+        *self.blocked.lock().unwrap()
+    }
+
+    /// blocks until the dock is not blocked
+    pub(crate) fn wait_unblocked(&self) {
+        let mut blocked = self.blocked.lock().unwrap();
+        while *blocked {
+            blocked = self.unblocked.wait(blocked).unwrap();
+        }
+    }
+
+    pub(crate) fn set_waker(&self, waker: &Waker) {
+        let mut slot = self.waker.lock().unwrap();
+        if !slot.as_ref().is_some_and(|slot| slot.will_wake(waker)) {
+            *slot = Some(waker.clone());
+        }
+    }
+
+    /// blocks until there is something new for the dock, `closed` is whether the dock already saw [`Shared::close`] being called
+    fn wait(&self, closed: bool) {
+        let mut pending = self.pending.lock().unwrap();
+        while pending.woken.is_empty() && pending.spawned.is_empty() && pending.closed == closed {
+            pending = self.idle.wait(pending).unwrap();
+        }
+    }
+}
+
+impl WakeHandle {
+    /// makes the parked stack runnable again, waking its dock up if it is idle
+    ///
+    /// waking a handle whose stack was already woken does nothing
+    pub fn wake(&self) {
+        self.shared.pending.lock().unwrap().woken.push(self.id);
+        self.shared.notify();
+    }
+}
+
+impl std::fmt::Debug for WakeHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WakeHandle").field("id", &self.id).finish()
+    }
+}
+
+/// replaces the dock of the current thread, returning the old one
+pub(crate) fn replace(dock: Option<Dock>) -> Option<Dock> {
+    DOCK.replace(dock)
+}
+
+/// docks `entry` with `dock` installed as the state of the dock, on `region` if one is passed and on the current stack otherwise
+///
+/// the dock is handed back once `entry` or whatever stack it switched to returns
+///
+/// ## SAFETY
+/// it is undefined behaviour to call this function inside a call to [`Stack::dock`]
+pub(crate) unsafe fn enter<T>(
+    mut dock: Dock,
+    region: Option<&Region>,
+    entry: impl FnOnce() -> T,
+) -> (Box<T>, Dock) {
+    use std::mem::ManuallyDrop;
+    unsafe extern "stdcall" fn fn_entry<F, T>(entry: *mut ManuallyDrop<F>) -> *mut T
+    where
+        F: FnOnce() -> T,
+    {
+        asan::arrived(std::ptr::null_mut());
+        trace::started();
+        let result =
+            unsafe { Box::into_raw(Box::new(ManuallyDrop::into_inner(std::ptr::read(entry))())) };
+        asan::returning_from_dock();
+        result
+    }
+
+    let mut entry = ManuallyDrop::new(entry);
+
+    dock.watch = region
+        .filter(|region| region.has_write_watch())
+        .map(WriteWatch::new);
+    let _span = trace::dock();
+    DEBUG_LINK.with(|_| ());
+    let previous = replace(Some(dock));
+    let previous_start = asm::current_stack_start();
+    let asan = asan::enter_dock(region.map(|region| (region.limit() as _, region.top() as _)));
+    let result = unsafe {
+        Box::from_raw(match region {
+            Some(region) => {
+                asm::dock_on(fn_entry, &mut entry as *mut _, region.top(), region.limit())
+            }
+            None => asm::dock(fn_entry, &mut entry as *mut _),
+        })
+    };
+    asan::leave_dock(asan);
+    asm::set_stack_start(previous_start);
+    let mut dock = replace(previous).expect("the dock was removed while docked");
+    // the coroutine that returned from the dock is not running anymore
+    dock.registry.spans.exit();
+    (result, dock)
+}
+
+/// runs `f` with the dock of the current thread
+///
+/// panics if called outside a call to [`Stack::dock`]
+pub(crate) fn with<R>(f: impl FnOnce(&mut Dock) -> R) -> R {
+    DOCK.with_borrow_mut(|dock| f(dock.as_mut().expect("not running inside of Stack::dock")))
+}
+
+/// the same as [`with`], but returns `None` instead of panicking outside a call to [`Stack::dock`]
+///
+/// it also returns `None` if called inside of [`with`], which can happen when a stack is dropped by the dock
+pub(crate) fn try_with<R>(f: impl FnOnce(&mut Dock) -> R) -> Option<R> {
+    DOCK.try_with(|dock| Some(f(dock.try_borrow_mut().ok()?.as_mut()?)))
+        .ok()
+        .flatten()
+}
+
+/// drops the snapshot of the write watch of the current dock, if it has one
+///
+/// must be called before anything other than that snapshot lands or restarts
+pub(crate) fn forget_snapshot() {
+    try_with(|dock| {
+        if let Some(watch) = &mut dock.watch {
+            watch.forget();
+        }
+    });
+}
+
+/// parks the stack in the current dock until the handle is woken
+///
+/// the stack is copied out of the dock if it was left there (something else lands next anyway), and compressed if the dock compresses parked stacks,
+/// then the oldest parked stacks are spilled if they take more than the budget of the dock
+pub(crate) fn park(handle: &WakeHandle, stack: Stack) {
+    // a live stack would stop counting its bytes as resident once copied out, and the dock would never see them leave
+    let mut stack = stack.into_owned();
+    if let Some((codec, threshold)) = with(|dock| dock.compression)
+        && stack.len() >= threshold
+    {
+        stack.compress(codec);
+    }
+    with(|dock| dock.park(handle, stack));
+    enforce_spill_budget();
+}
+
+/// spills the oldest parked stacks of the current dock until the ones left in memory fit in its budget, see [`Stack::set_spill_budget`]
+pub(crate) fn enforce_spill_budget() {
+    loop {
+        // the stack is taken out of the dock while it is spilled, dropping its buffer gives it back to the pool
+        let next = with(|dock| {
+            let (file, budget) = dock.spill.clone()?;
+            if dock.parked_resident <= budget {
+                return None;
+            }
+            let id = *dock
+                .parked
+                .range(dock.spill_cursor..)
+                .find(|(_, stack)| stack.resident_len() != 0)?
+                .0;
+            dock.spill_cursor = id + 1;
+            let stack = dock.parked.remove(&id)?;
+            dock.parked_resident -= stack.resident_len();
+            Some((file, id, stack))
+        });
+        let Some((file, id, mut stack)) = next else {
+            return;
+        };
+        // if the file cannot be written to, the stack just stays in memory
+        let spilled = stack.spill(&file).is_ok();
+        with(|dock| {
+            dock.parked_resident += stack.resident_len();
+            dock.parked.insert(id, stack);
+        });
+        if !spilled {
+            return;
+        }
+    }
+}
+
+/// resumes the next runnable stack of the current dock, waiting for a wake if every stack is parked (or leaving the dock, see [`Idle`])
+///
+/// if nothing is runnable or parked, `on_empty` is called instead
+///
+/// ## SAFETY
+/// the same as [`Stack::resume`]
+pub(crate) unsafe fn switch(on_empty: impl FnOnce() -> std::convert::Infallible) -> ! {
+    loop {
+        if with(|dock| dock.registry.take_dump_request()) {
+            let _ = Stack::dump_coroutines(&mut std::io::stderr());
+        }
+        let next = with(|dock| {
+            dock.drain_pending();
+            let serving = dock.idle == Idle::Serve && !dock.closed;
+            match dock.run_queue.pop_front() {
+                Some(stack) => Next::Run(stack),
+                None if dock.parked.is_empty() && !serving => Next::Empty,
+                None if dock.idle == Idle::Leave => Next::Leave,
+                None => Next::Wait(dock.shared.clone(), dock.closed),
+            }
+        });
+        match next {
+            Next::Run(stack) => unsafe { Stack::resume(stack) },
+            Next::Wait(shared, closed) => shared.wait(closed),
+            Next::Leave => unsafe { Stack::restart(|| ()) },
+            Next::Empty => match on_empty() {},
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! a multi-threaded scheduler, with one dock per worker thread
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::JoinHandle;
+
+use crate::dock::{self, Dock, Idle, Shared};
+use crate::{Pressure, Stack};
+
+/// runs coroutines on a fixed number of worker threads, each with its own dock and run queue
+///
+/// a coroutine stays on the worker it was spawned to for its whole life, since suspended stacks can only be resumed by the dock they were suspended from,
+/// the worker is picked when spawning, as the one with the fewest live coroutines
+///
+/// dropping the executor waits for every coroutine to finish, including the parked ones
+pub struct Executor {
+    workers: Vec<Worker>,
+}
+
+struct Worker {
+    shared: Arc<Shared>,
+    /// the number of coroutines spawned to this worker that did not finish yet
+    load: Arc<AtomicUsize>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Executor {
+    /// starts an executor with `threads` worker threads
+    ///
+    /// panics if `threads` is zero
+    pub fn new(threads: usize) -> Self {
+        Executor::start(threads, None)
+    }
+
+    /// the same as [`Executor::new`], but the dock of every worker gets a memory budget, see [`Stack::set_memory_budget`]
+    ///
+    /// with [`Pressure::Block`], [`Executor::spawn`] waits until some worker has room again
+    pub fn with_memory_budget(
+        threads: usize,
+        limit: usize,
+        policy: impl Fn(&mut Stack) -> Pressure + Send + Sync + 'static,
+    ) -> Self {
+        let policy = Arc::new(policy);
+        Executor::start(
+            threads,
+            Some(Arc::new(move || {
+                let policy = policy.clone();
+                Stack::set_memory_budget(limit, move |stack| policy(stack));
+            })),
+        )
+    }
+
+    /// `setup` runs inside the dock of every worker, before it starts serving
+    fn start(threads: usize, setup: Option<Arc<dyn Fn() + Send + Sync>>) -> Self {
+        assert!(threads > 0, "an executor needs at least one worker");
+        let workers = (0..threads)
+            .map(|index| {
+                let shared = Arc::new(Shared::new());
+                let thread = std::thread::Builder::new()
+                    .name(format!("stack-master-worker-{index}"))
+                    .spawn({
+                        let shared = shared.clone();
+                        let setup = setup.clone();
+                        move || {
+                            let mut dock = Dock::with_shared(shared);
+                            dock.idle = Idle::Serve;
+                            unsafe {
+                                dock::enter(dock, None, || -> () {
+                                    if let Some(setup) = setup {
+                                        setup();
+                                    }
+                                    dock::switch(|| Stack::restart(|| ()))
+                                });
+                            }
+                        }
+                    })
+                    .expect("failed to spawn a worker thread");
+                Worker {
+                    shared,
+                    load: Arc::new(AtomicUsize::new(0)),
+                    thread: Some(thread),
+                }
+            })
+            .collect();
+        Executor { workers }
+    }
+
+    /// the number of worker threads
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// runs the entry function in a new coroutine, on the least loaded worker
+    ///
+    /// workers that are over their memory budget with [`Pressure::Block`] are skipped, if every worker is, this waits for the least loaded one
+    ///
+    /// the coroutine may use [`Stack::suspend`], [`Stack::yield_now`], [`Stack::park`] and the like, but it must not leave its dock with [`Stack::restart`]
+    ///
+    /// ## SAFETY
+    /// it is undefined behaviour for entry to unwind
+    #[track_caller]
+    pub unsafe fn spawn<F>(&self, entry: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let least_loaded = |worker: &&Worker| worker.load.load(Ordering::Relaxed);
+        let worker = match self
+            .workers
+            .iter()
+            .filter(|worker| !worker.shared.is_blocked())
+            .min_by_key(least_loaded)
+        {
+            Some(worker) => worker,
+            None => {
+                let worker = self
+                    .workers
+                    .iter()
+                    .min_by_key(least_loaded)
+                    .expect("an executor has at least one worker");
+                worker.shared.wait_unblocked();
+                worker
+            }
+        };
+        let load = worker.load.clone();
+        load.fetch_add(1, Ordering::Relaxed);
+        worker.shared.spawn(
+            Box::new(move || {
+                entry();
+                load.fetch_sub(1, Ordering::Relaxed);
+                unsafe { Stack::run_next(|| ()) }
+            }),
+            std::panic::Location::caller(),
+        );
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        for worker in &self.workers {
+            worker.shared.close();
+        }
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
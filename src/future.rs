@@ -0,0 +1,138 @@
+//! adapters between stackful coroutines and [`std::future::Future`]
+
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+use crate::Stack;
+use crate::dock::{self, Dock, Idle, WakeHandle};
+use crate::region::Region;
+use crate::registry;
+
+/// a future that runs a coroutine each time it is polled, until the coroutine finishes
+///
+/// the coroutine runs in its own dock, on a region owned by the future, so every poll lands it at the same address
+///
+/// inside the coroutine [`Stack::await_future`] makes the poll return [`Poll::Pending`] until the awaited future wakes it
+pub struct CoroutineFuture<T> {
+    region: Region,
+    /// `None` once the coroutine finished
+    dock: Option<Dock>,
+    output: Rc<Cell<Option<T>>>,
+}
+
+impl<T: 'static> CoroutineFuture<T> {
+    /// the size of the region used by [`CoroutineFuture::new`]
+    pub const DEFAULT_STACK_SIZE: usize = 256 * 1024;
+
+    /// wraps a coroutine that will run the specified entry function
+    ///
+    /// ## SAFETY
+    /// it is undefined behaviour to:
+    /// - poll this future inside a call to [`Stack::dock`]
+    /// - for entry to unwind
+    /// - for the coroutine to use more than [`CoroutineFuture::DEFAULT_STACK_SIZE`] bytes of stack
+    #[track_caller]
+    pub unsafe fn new<F>(entry: F) -> Self
+    where
+        F: FnOnce() -> T + 'static,
+    {
+        unsafe { Self::with_stack_size(Self::DEFAULT_STACK_SIZE, entry) }
+    }
+
+    /// the same as [`CoroutineFuture::new`], but the coroutine gets `stack_size` bytes of stack
+    ///
+    /// ## SAFETY
+    /// the same as [`CoroutineFuture::new`], except for the size of the stack
+    #[track_caller]
+    pub unsafe fn with_stack_size<F>(stack_size: usize, entry: F) -> Self
+    where
+        F: FnOnce() -> T + 'static,
+    {
+        let output = Rc::new(Cell::new(None));
+        let slot = output.clone();
+        let mut dock = Dock::new();
+        dock.idle = Idle::Leave;
+        // the coroutine belongs to the dock of the future, not to the current one
+        let id = registry::new_id();
+        dock.registry
+            .created(id, None, std::panic::Location::caller());
+        // the dock always has the output type `()`, the actual output goes through the slot
+        dock.run_queue.push_back(unsafe {
+            Stack::from_entry_with_id(move || slot.set(Some(entry())), Some(id))
+        });
+        CoroutineFuture {
+            region: Region::new(stack_size)
+                .expect("failed to allocate the region of a CoroutineFuture"),
+            dock: Some(dock),
+            output,
+        }
+    }
+}
+
+impl<T> Future for CoroutineFuture<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let this = &mut *self;
+        let dock = this
+            .dock
+            .take()
+            .expect("CoroutineFuture polled after completion");
+        dock.shared.set_waker(cx.waker());
+        let (_, dock) = unsafe {
+            dock::enter(dock, Some(&this.region), || -> () {
+                dock::switch(|| Stack::restart(|| ()))
+            })
+        };
+        if let Some(output) = this.output.take() {
+            return Poll::Ready(output);
+        }
+        assert!(
+            !dock.run_queue.is_empty() || !dock.parked.is_empty(),
+            "the coroutine of a CoroutineFuture was discarded without finishing"
+        );
+        this.dock = Some(dock);
+        Poll::Pending
+    }
+}
+
+impl Wake for WakeHandle {
+    fn wake(self: Arc<Self>) {
+        WakeHandle::wake(&self)
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        WakeHandle::wake(self)
+    }
+}
+
+impl Stack {
+    /// suspends the current stack until the future is ready, resuming the next stack of the run queue in the meantime
+    ///
+    /// the future is polled with a [`Waker`] that schedules the current stack again
+    ///
+    /// ## SAFETY
+    /// the same as [`Stack::suspend`]
+    pub unsafe fn await_future<F: Future>(fut: F) -> F::Output {
+        let mut fut = std::pin::pin!(fut);
+        let handle = dock::with(|dock| dock.handle());
+        let waker = Waker::from(Arc::new(handle.clone()));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+                return output;
+            }
+            let handle = handle.clone();
+            unsafe {
+                Stack::suspend(move |stack| {
+                    dock::park(&handle, stack);
+                    dock::switch(|| unreachable!("a stack was just parked"))
+                })
+            }
+        }
+    }
+}
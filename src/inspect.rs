@@ -0,0 +1,158 @@
+//! a look inside suspended stacks, to see where a coroutine is waiting
+
+use std::borrow::Cow;
+use std::io;
+
+use crate::chunk::ChunkStore;
+use crate::{Stack, StackImpl};
+
+/// a frame of a suspended stack, with addresses as they are once the stack lands
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// the value of `ebp` in the frame, where the frame pointer of its caller is saved
+    pub frame_pointer: *const u8,
+    /// where the function of the frame returns to, somewhere in its caller
+    pub return_address: *const u8,
+}
+
+/// the frames of a suspended stack, from the innermost one (the one that called [`Stack::suspend`]) out to the dock, see [`Stack::frames`]
+///
+/// the walk follows the chain of saved `ebp`s, so it stops early at the first function that does not keep a frame pointer
+pub struct Frames<'a> {
+    bytes: Cow<'a, [u8]>,
+    /// the address the first byte lands at
+    base: usize,
+    /// the frame pointer of the next frame, and the offset in `bytes` of its return address
+    next: Option<(usize, usize)>,
+}
+
+/// `suspend` pushes the return address, then `ebp`, `ebx`, `esi` and `edi`
+const SAVED_EBP: usize = 12;
+const SAVED_RETURN_ADDRESS: usize = 16;
+
+impl Frames<'_> {
+    fn read(&self, offset: usize) -> Option<usize> {
+        let bytes = self.bytes.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    }
+}
+
+impl Iterator for Frames<'_> {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        let (frame_pointer, return_offset) = self.next.take()?;
+        let return_address = self.read(return_offset)?;
+        // the caller's frame is further from the top of the stack, anything else is not a frame pointer
+        let offset = frame_pointer.wrapping_sub(self.base);
+        if offset > return_offset && offset + 8 <= self.bytes.len() {
+            self.next = self
+                .read(offset)
+                .map(|caller_frame_pointer| (caller_frame_pointer, offset + 4));
+        }
+        Some(Frame {
+            frame_pointer: frame_pointer as *const u8,
+            return_address: return_address as *const u8,
+        })
+    }
+}
+
+impl Stack {
+    /// the number of bytes the stack takes once landed, zero for stacks that were never suspended
+    pub fn len(&self) -> usize {
+        match self.inner {
+            StackImpl::Boxed(ref bytes) => bytes.len(),
+            StackImpl::Inline { len, .. }
+            | StackImpl::Tracked { len, .. }
+            | StackImpl::Compressed { len, .. }
+            | StackImpl::Spilled { len, .. }
+            | StackImpl::Chunked { len, .. }
+            | StackImpl::External { len, .. } => len,
+            StackImpl::Live(ref live) => live.len,
+            StackImpl::Empty { .. } => 0,
+        }
+    }
+
+    /// whether the stack was never suspended, and would start from its entry function
+    pub fn is_empty(&self) -> bool {
+        matches!(self.inner, StackImpl::Empty { .. })
+    }
+
+    /// the address of the dock the stack was suspended from, the stack lands right below it
+    ///
+    /// null for stacks that were never suspended, those can land anywhere
+    pub fn landing_address(&self) -> *const u8 {
+        self.start
+    }
+
+    /// the bytes of the stack, as they are once landed
+    ///
+    /// compressed, spilled and deduplicated stacks have to be put back together for this, which fails if the stack is spilled and the file cannot be read
+    pub fn as_bytes(&self) -> io::Result<Cow<'_, [u8]>> {
+        Ok(match self.inner {
+            StackImpl::Boxed(ref bytes) => Cow::Borrowed(bytes),
+            StackImpl::Inline { len, ref bytes } => Cow::Borrowed(unsafe {
+                std::slice::from_raw_parts(bytes.as_ptr() as *const u8, len)
+            }),
+            StackImpl::Tracked { ref bytes, len } => Cow::Borrowed(&bytes[bytes.len() - len..]),
+            StackImpl::External { data, len } => {
+                Cow::Borrowed(unsafe { std::slice::from_raw_parts(data, len) })
+            }
+            StackImpl::Live(ref live) => match *live.copy.borrow() {
+                Some(ref copy) => Cow::Owned(copy.as_bytes()?.into_owned()),
+                // nothing landed since the stack was suspended, and nothing can land while it is borrowed
+                None => Cow::Borrowed(unsafe { std::slice::from_raw_parts(live.data, live.len) }),
+            },
+            StackImpl::Compressed {
+                ref bytes,
+                len,
+                codec,
+            } => {
+                let mut out = Vec::with_capacity(len);
+                codec.decompress(bytes, &mut out);
+                Cow::Owned(out)
+            }
+            StackImpl::Spilled {
+                ref file,
+                offset,
+                stored,
+                len,
+                codec,
+            } => {
+                let mut out = Vec::with_capacity(stored);
+                file.read(offset, stored, &mut out)?;
+                if let Some(codec) = codec {
+                    let mut decompressed = Vec::with_capacity(len);
+                    codec.decompress(&out, &mut decompressed);
+                    out = decompressed;
+                }
+                Cow::Owned(out)
+            }
+            StackImpl::Chunked { ref chunks, len } => {
+                let mut out = Vec::with_capacity(len);
+                ChunkStore::join(chunks, &mut out);
+                Cow::Owned(out)
+            }
+            StackImpl::Empty { .. } => Cow::Borrowed(&[]),
+        })
+    }
+
+    /// the frames of the stack, from the one that suspended it out to the dock, see [`Frames`]
+    ///
+    /// the addresses are where the frames are once the stack lands, stacks that were never suspended have none,
+    /// and neither do spilled stacks whose file cannot be read, see [`Stack::as_bytes`]
+    pub fn frames(&self) -> Frames<'_> {
+        let bytes = self.as_bytes().unwrap_or_default();
+        let base = (self.start as usize).wrapping_sub(bytes.len());
+        let mut frames = Frames {
+            bytes,
+            base,
+            next: None,
+        };
+        frames.next = frames
+            .read(SAVED_EBP)
+            .filter(|_| frames.bytes.len() >= SAVED_RETURN_ADDRESS + 4)
+            .map(|frame_pointer| (frame_pointer, SAVED_RETURN_ADDRESS));
+        frames
+    }
+}
@@ -1,14 +1,101 @@
+mod asan;
 mod asm;
+mod backtrace;
+mod budget;
+mod chunk;
+mod compress;
+mod dock;
+mod executor;
+mod future;
+mod inspect;
+mod live;
+mod poison;
+mod pool;
+mod region;
+mod registry;
+mod send;
+mod spill;
+mod stats;
+mod sys;
 #[cfg(test)]
 mod tests;
+mod trace;
+mod watch;
+
+pub use backtrace::{Backtrace, BacktraceFrame};
+pub use budget::{BudgetExceeded, Pressure};
+pub use chunk::ChunkStats;
+pub use compress::{Codec, Lz};
+pub use dock::WakeHandle;
+pub use executor::Executor;
+pub use future::CoroutineFuture;
+pub use inspect::{Frame, Frames};
+pub use poison::{Escape, ScannedObject};
+pub use pool::PoolStats;
+pub use region::Region;
+pub use registry::{Builder, CoroutineId, CoroutineInfo, CoroutineState, request_dump};
+pub use send::{SendStack, SharedRegion};
+pub use spill::SpillFile;
+pub use stats::{DockStats, SIZE_BUCKETS};
+
+use std::rc::Rc;
+
+/// stacks of up to this many bytes can be stored inline, without a buffer from the pool
+pub const INLINE_CAPACITY: usize = 512;
+
+thread_local! {
+    /// where inline stacks are copied to before landing, the `Stack` itself is on the stack that gets overwritten
+    static INLINE_LANDING: std::cell::UnsafeCell<[std::mem::MaybeUninit<u8>; INLINE_CAPACITY]> =
+        const { std::cell::UnsafeCell::new([std::mem::MaybeUninit::uninit(); INLINE_CAPACITY]) };
+}
 
 /// The `Stack` type represents a saved stack which can be resumed later.
 ///
 /// See it's static methods for more
-pub struct Stack(StackImpl);
+pub struct Stack {
+    inner: StackImpl,
+    /// the address of the dock the stack was suspended from, it can only land there
+    ///
+    /// null for stacks that were never suspended, those can land anywhere
+    start: *const u8,
+    /// the bytes of the stack that are counted in the suspended bytes of the dock, see [`Stack::suspended_bytes`]
+    accounted: usize,
+    /// the coroutine the stack belongs to, `None` for the copies the crate makes internally
+    id: Option<CoroutineId>,
+}
 
+// the inline variant is meant to be large, it saves an allocation for small stacks
+#[allow(clippy::large_enum_variant)]
 enum StackImpl {
-    Boxed(Box<[u8]>),
+    /// the capacity of the buffer is its size class in the pool
+    Boxed(Vec<u8>),
+    /// small stacks are kept in the `Stack` itself, see [`Stack::set_inline_threshold`]
+    Inline {
+        len: usize,
+        bytes: [std::mem::MaybeUninit<u8>; INLINE_CAPACITY],
+    },
+    /// bytes that are still on the dock, see [`LiveStack`](live::LiveStack)
+    Live(std::rc::Rc<live::LiveStack>),
+    /// the last `len` bytes of a buffer that is reused every time the stack lands and is suspended again, see [`Region::with_write_watch`]
+    Tracked { bytes: Box<[u8]>, len: usize },
+    /// `len` bytes compressed with `codec`, see [`Stack::compress`]
+    Compressed {
+        bytes: Box<[u8]>,
+        len: usize,
+        codec: &'static dyn Codec,
+    },
+    /// `stored` bytes written to `file`, compressed with `codec` if there is one, see [`Stack::spill`]
+    Spilled {
+        file: Rc<SpillFile>,
+        offset: u64,
+        stored: usize,
+        len: usize,
+        codec: Option<&'static dyn Codec>,
+    },
+    /// `len` bytes split into chunks that may be shared with other stacks, see [`Stack::set_deduplication`]
+    Chunked { chunks: Vec<Rc<[u8]>>, len: usize },
+    /// bytes in a buffer owned by the caller of [`Stack::suspend_into`]
+    External { data: *const u8, len: usize },
     Empty {
         f: unsafe extern "stdcall" fn(*mut ()) -> *mut (),
         a: *mut (),
@@ -22,26 +109,67 @@ impl Stack {
     /// ## SAFETY
     /// it is undefined behaviour to call this function inside a call to [`Stack::dock`]
     pub unsafe fn dock<T>(entry: impl FnOnce() -> T + 'static) -> Box<T> {
-        use std::mem::ManuallyDrop;
-        unsafe extern "stdcall" fn fn_entry<F, T>(entry: *mut ManuallyDrop<F>) -> *mut T
-        where
-            F: FnOnce() -> T,
-        {
-            unsafe { Box::into_raw(Box::new(ManuallyDrop::into_inner(std::ptr::read(entry))())) }
-        }
-
-        let mut entry = ManuallyDrop::new(entry);
+        unsafe { dock::enter(dock::Dock::new(), None, entry).0 }
+    }
 
-        unsafe { Box::from_raw(asm::dock(fn_entry, &mut entry as *mut _)) }
+    /// the same as [`Stack::dock`], but the entry function and everything docked runs on the region instead of the stack of the current thread
+    ///
+    /// the dock always starts at [`Region::top`], so stacks suspended in this dock can be resumed by a later call to this function with the same region
+    ///
+    /// ## SAFETY
+    /// it is undefined behaviour to:
+    /// - call this function inside a call to [`Stack::dock`]
+    /// - use more stack than [`Region::len`] while docked
+    /// - call this function with a region that some other thread is docked on
+    pub unsafe fn dock_on<T>(region: &Region, entry: impl FnOnce() -> T + 'static) -> Box<T> {
+        unsafe { dock::enter(dock::Dock::new(), Some(region), entry).0 }
     }
 
     /// creates a new stack that when resumed will run the specified entry function
     ///
     /// if the passed function returns, when this stack is being executed after being resumed, [`Stack::dock`] will quit and return that value
     ///
+    /// the coroutine is registered in the current dock, if there is one, see [`Stack::coroutines`]
+    ///
     /// ## SAFETY
     /// it is undefined behaviour for entry to unwind
+    #[track_caller]
     pub unsafe fn from_entry<F, T>(entry: F) -> Stack
+    where
+        F: FnOnce() -> T + 'static,
+    {
+        unsafe { Stack::builder().from_entry(entry) }
+    }
+
+    /// creates stacks with a name for the registry, see [`Builder`]
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// the id of the coroutine the stack belongs to, `None` for stacks that did not get one because they were created outside of a dock
+    pub fn id(&self) -> Option<CoroutineId> {
+        self.id
+    }
+
+    /// the same as [`Stack::from_entry`], but with the name and location for the registry
+    pub(crate) unsafe fn from_entry_at<F, T>(
+        entry: F,
+        name: Option<String>,
+        location: &'static std::panic::Location<'static>,
+    ) -> Stack
+    where
+        F: FnOnce() -> T + 'static,
+    {
+        // outside of a dock there is nowhere to register the coroutine, it gets an id once it is first suspended in one
+        let id = dock::try_with(|_| registry::new_id());
+        if let Some(id) = id {
+            registry::created(id, name, location);
+        }
+        unsafe { Stack::from_entry_with_id(entry, id) }
+    }
+
+    /// the same as [`Stack::from_entry`], but the coroutine is not registered anywhere
+    pub(crate) unsafe fn from_entry_with_id<F, T>(entry: F, id: Option<CoroutineId>) -> Stack
     where
         F: FnOnce() -> T + 'static,
     {
@@ -53,11 +181,16 @@ impl Stack {
         };
         let a = Box::into_raw(Box::new(entry)) as *mut ();
 
-        Stack(StackImpl::Empty {
-            f,
-            a,
-            drop_a: boxed_drop::<F>,
-        })
+        Stack {
+            inner: StackImpl::Empty {
+                f,
+                a,
+                drop_a: boxed_drop::<F>,
+            },
+            start: std::ptr::null(),
+            accounted: 0,
+            id,
+        }
     }
 
     /// discards the current stack without unwinding or running destructors, and replaces it with a call to entry
@@ -71,6 +204,11 @@ impl Stack {
     /// - call this function inside a call to [`Stack::dock`]
     /// - for entry to unwind
     pub unsafe fn restart<T>(entry: impl FnOnce() -> T + 'static) -> ! {
+        registry::switch_to(None);
+        live::materialize();
+        dock::forget_snapshot();
+        trace::restarted(None);
+        asan::leaving();
         unsafe { asm::restart(boxed_entry, Box::into_raw(Box::new(entry))) }
     }
 
@@ -78,6 +216,9 @@ impl Stack {
     ///
     /// the callback serves as an opportunity to call [`Stack::restart`] to start a new
     ///
+    /// if the memory budget of the dock rejects the suspension (see [`Stack::set_memory_budget`]), the callback is dropped without being called and this returns right away,
+    /// use [`Stack::try_suspend`] to tell the two apart
+    ///
     /// ## SAFETY
     /// it is undefined behaviour to:
     /// - call this function outside a call to [`Stack::dock`]
@@ -87,68 +228,305 @@ impl Stack {
     where
         F: FnOnce(Stack) -> std::convert::Infallible + 'static,
     {
+        let _ = unsafe { Stack::try_suspend(f) };
+    }
+
+    /// the same as [`Stack::suspend`], but returns an error if the memory budget of the dock rejected the suspension
+    ///
+    /// ## SAFETY
+    /// the same as [`Stack::suspend`]
+    pub unsafe fn try_suspend<F>(f: F) -> Result<(), BudgetExceeded>
+    where
+        F: FnOnce(Stack) -> std::convert::Infallible + 'static,
+    {
+        type Context<F> = (
+            std::mem::ManuallyDrop<F>,
+            Result<(), BudgetExceeded>,
+            asan::FakeStack,
+        );
+
         // The trampoline matches the callback signature expected by `asm::suspend`.
         // It is nested and generic over F so we can move the actual closure in-place.
         unsafe extern "stdcall" fn suspend_trampoline<F>(
             stack_data: *const u8,
             stack_len: usize,
-            fn_ptr: *mut F,
+            context: *mut Context<F>,
         ) where
             F: FnOnce(Stack) -> std::convert::Infallible,
         {
-            println!("stack_data = {stack_data:?}");
-            println!("stack_len = {stack_len:?}");
-            println!("fn_ptr = {fn_ptr:?}");
+            asan::unpoison(stack_data, stack_len);
+            // the context is part of the bytes of the stack, so this must happen before they are copied anywhere
+            asan::suspending(unsafe { &mut (*context).2 });
             // Safety: we're called from the special assembly `suspend` which
-            // provides a valid `stack_data` and `stack_len`. We allocate on the heap
-            // and copy the bytes out of the current stack region into the heap buffer.
-            let coroutine = unsafe { Stack::from_parts_copied(stack_data, stack_len) };
+            // provides a valid `stack_data` and `stack_len`. We leave the bytes where they are,
+            // the dock copies them out before anything overwrites them.
+            let mut coroutine = unsafe { Stack::from_parts_live(stack_data, stack_len) };
+            coroutine.id = Some(registry::suspended(stack_len));
+
+            // The closure lives in the frame of `suspend`, which is not overwritten until
+            // the callback lands or restarts something, so we move it out before calling it.
+            let f = unsafe { std::ptr::read(&(*context).0) };
+
+            if let Err(error) = budget::apply(&mut coroutine) {
+                // returning makes `asm::suspend` return as if the stack was resumed right away
+                unsafe { (*context).1 = Err(error) };
+                registry::switch_to(coroutine.id.take());
+                asan::leaving();
+                drop(std::mem::ManuallyDrop::into_inner(f));
+                return;
+            }
 
-            let f = unsafe { Box::from_raw(fn_ptr) };
+            poison::vacated(stack_data as *mut u8, stack_len, coroutine.id);
 
             // call the user's closure; it returns `Infallible` (never), so we never return.
-            let _ = f(coroutine);
+            #[allow(unreachable_code)]
+            let _ = std::mem::ManuallyDrop::into_inner(f)(coroutine);
         }
 
+        let mut context: Context<F> =
+            (std::mem::ManuallyDrop::new(f), Ok(()), std::ptr::null_mut());
         unsafe {
-            let f = Box::into_raw(Box::new(f));
-            // call the assembly helper which will call our trampoline with (stack_data, stack_len, &mut entry)
-            asm::suspend(suspend_trampoline::<F>, f);
+            // call the assembly helper which will call our trampoline with (stack_data, stack_len, &mut context)
+            asm::suspend(suspend_trampoline::<F>, &mut context as *mut Context<F>);
         }
+        asan::arrived(context.2);
+        context.1
+    }
+
+    /// the same as [`Stack::suspend`], but the bytes of the stack are stored in `buffer` instead of a buffer from the pool of the dock
+    ///
+    /// if `buffer` has enough capacity for the stack, no buffer is allocated for its bytes, neither to suspend nor to resume it
+    ///
+    /// that is all this saves, registering the coroutine the first time it is suspended (see [`Stack::coroutines`]) and the `tracing` spans may still allocate
+    ///
+    /// ## SAFETY
+    /// it is undefined behaviour to:
+    /// - call this function outside a call to [`Stack::dock`]
+    /// - for f to unwind
+    /// - call this function in the callback of another call to suspend
+    /// - pass a buffer whose bytes are on the stack of the dock
+    /// - drop, grow or modify the buffer until the stack is resumed or dropped
+    pub unsafe fn suspend_into<F>(buffer: &mut Vec<u8>, f: F)
+    where
+        F: FnOnce(Stack) -> std::convert::Infallible + 'static,
+    {
+        type Context<F> = (std::mem::ManuallyDrop<F>, *mut Vec<u8>, asan::FakeStack);
+
+        unsafe extern "stdcall" fn suspend_into_trampoline<F>(
+            stack_data: *const u8,
+            stack_len: usize,
+            context: *mut Context<F>,
+        ) where
+            F: FnOnce(Stack) -> std::convert::Infallible,
+        {
+            asan::unpoison(stack_data, stack_len);
+            asan::suspending(unsafe { &mut (*context).2 });
+            // Like in `suspend`, the context is still intact until the callback is called.
+            let (f, buffer) = unsafe { (std::ptr::read(&(*context).0), (*context).1) };
+            let buffer = unsafe { &mut *buffer };
+            let timer = trace::CopyTimer::start();
+            buffer.clear();
+            buffer.extend_from_slice(unsafe { std::slice::from_raw_parts(stack_data, stack_len) });
+            trace::copied_out(stack_len, timer);
+            let coroutine = Stack {
+                inner: StackImpl::External {
+                    data: buffer.as_ptr(),
+                    len: stack_len,
+                },
+                start: unsafe { stack_data.add(stack_len) },
+                // the buffer belongs to the caller
+                accounted: 0,
+                id: Some(registry::suspended(stack_len)),
+            };
+            poison::vacated(stack_data as *mut u8, stack_len, coroutine.id);
+
+            // call the user's closure; it returns `Infallible` (never), so we never return.
+            #[allow(unreachable_code)]
+            let _ = std::mem::ManuallyDrop::into_inner(f)(coroutine);
+        }
+
+        let mut context: Context<F> =
+            (std::mem::ManuallyDrop::new(f), buffer, std::ptr::null_mut());
+        unsafe {
+            asm::suspend(suspend_into_trampoline::<F>, &mut context);
+        }
+        asan::arrived(context.2);
     }
 
     /// discards the current stack without unwinding or running destructors, and replaces it with the specified stack, consuming it
     ///
+    /// if the stack was spilled (see [`Stack::spill`]) and its bytes cannot be read back, or it was suspended from a different dock, this aborts the process, use [`Stack::try_resume`] to handle that
+    ///
     /// it is undefined behaviour to:
     ///
     /// ## SAFETY
     /// - call this function outside a call to [`Stack::dock`]
     /// - call this function with a stack suspended from a different call to [`Stack::dock`]
     /// - call this function with a stack that was created with a output type that is different from the output type of [`Stack::dock`]
-    pub unsafe fn resume(mut stack: Stack) -> ! {
-        unsafe extern "stdcall" fn land_drop_coroutine_trampoline(
+    pub unsafe fn resume(stack: Stack) -> ! {
+        match unsafe { Stack::try_resume(stack) } {
+            Err((_, error)) => {
+                // unwinding from here would go through the frames of the asm, and lose the stack anyway
+                eprintln!("could not resume a stack: {error}");
+                std::process::abort()
+            }
+        }
+    }
+
+    /// the same as [`Stack::resume`], but if the stack was spilled and its bytes cannot be read back, this returns the error together with the stack, which is left as it was
+    ///
+    /// so does a stack suspended from a different dock, with an error of kind [`std::io::ErrorKind::InvalidInput`]
+    ///
+    /// ## SAFETY
+    /// the same as [`Stack::resume`]
+    #[allow(clippy::result_large_err)]
+    pub unsafe fn try_resume(
+        mut stack: Stack,
+    ) -> Result<std::convert::Infallible, (Stack, std::io::Error)> {
+        unsafe extern "stdcall" fn land_recycle_trampoline(
             stack_data: *const u8,
             stack_len: usize,
-            _: *mut (),
+            capacity: *mut (),
         ) {
+            landed(stack_len);
             unsafe {
-                drop(Stack::from_parts_owned(stack_data as *mut u8, stack_len));
+                pool::recycle(Vec::from_raw_parts(
+                    stack_data as *mut u8,
+                    stack_len,
+                    capacity as usize,
+                ));
+            }
+        }
+
+        unsafe extern "stdcall" fn land_noop_trampoline(
+            _: *const u8,
+            stack_len: usize,
+            _: *mut (),
+        ) {
+            landed(stack_len);
+        }
+
+        unsafe extern "stdcall" fn land_snapshot_trampoline(
+            stack_data: *const u8,
+            stack_len: usize,
+            snapshot_len: *mut (),
+        ) {
+            landed(stack_len);
+            let snapshot_len = snapshot_len as usize;
+            let bytes = unsafe {
+                Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                    stack_data.add(stack_len).sub(snapshot_len) as *mut u8,
+                    snapshot_len,
+                ))
+            };
+            dock::try_with(|dock| {
+                if let Some(watch) = &mut dock.watch {
+                    watch.landed(bytes, stack_len);
+                }
+            });
+        }
+
+        /// tells asan about the `stack_len` bytes that just landed, called by the callbacks of `asm::resume`
+        fn landed(stack_len: usize) {
+            let data = asm::current_stack_start().wrapping_sub(stack_len);
+            asan::unpoison(data, stack_len);
+            trace::landed(stack_len);
+        }
+
+        // landing over bytes that belong to some other dock would corrupt both stacks, so this is checked before anything is switched
+        let on_this_dock = match stack.inner {
+            StackImpl::Live(ref live) => live.copy.borrow().is_some() || live.is_on_dock(),
+            _ => true,
+        } && (stack.start.is_null()
+            || stack.start == asm::current_stack_start());
+        if !on_this_dock {
+            let error = std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "resumed a stack suspended from a different dock",
+            );
+            return Err((stack, error));
+        }
+
+        // this is the only other part that can fail, so it also goes before anything is switched
+        if let Err(error) = stack.load() {
+            return Err((stack, error));
+        }
+
+        // once landed, the bytes are not held by a suspended stack anymore
+        stack.unaccount();
+
+        if let StackImpl::Live(ref live) = stack.inner {
+            let copy = live.copy.borrow_mut().take();
+            let len = live.len;
+            let id = stack.id.take();
+            drop(stack);
+            match copy {
+                // something landed since the stack was suspended, so it was copied out
+                Some(mut copy) => {
+                    copy.id = id;
+                    unsafe { Stack::resume(copy) }
+                }
+                // the stack was just suspended, and its bytes are still on the dock
+                None => {
+                    registry::switch_to(id);
+                    // nothing to copy
+                    trace::landing(id);
+                    trace::landed(0);
+                    asan::leaving();
+                    unsafe { asm::resume_in_place(len) }
+                }
             }
         }
 
-        match stack.0 {
+        let id = stack.id.take();
+        registry::switch_to(id);
+
+        // whatever lands now is going to overwrite the stack that was just suspended
+        live::materialize();
+        // and it is not the snapshot the dock has, if it has one
+        dock::forget_snapshot();
+
+        stack.unshare();
+        stack.decompress();
+
+        match stack.inner {
+            StackImpl::Empty { .. } => trace::restarted(id),
+            _ => trace::landing(id),
+        }
+        asan::leaving();
+        match stack.inner {
+            StackImpl::Tracked { ref mut bytes, len } => {
+                let bytes = Box::into_raw(std::mem::take(bytes));
+                unsafe {
+                    // The callback gives the buffer back to the write watch of the dock, which
+                    // needs its length to find it again from the end of the stack.
+                    asm::resume(
+                        (bytes as *const u8).add(bytes.len() - len),
+                        len,
+                        bytes.len() as *mut (),
+                        land_snapshot_trampoline,
+                    )
+                }
+            }
+            StackImpl::Inline { len, ref bytes } => unsafe {
+                let landing = INLINE_LANDING.with(|landing| landing.get() as *mut u8);
+                std::ptr::copy_nonoverlapping(bytes.as_ptr() as *const u8, landing, len);
+                asm::resume(landing, len, std::ptr::null_mut(), land_noop_trampoline)
+            },
+            StackImpl::External { data, len } => unsafe {
+                // The buffer belongs to the caller of `suspend_into`, there is nothing to free.
+                asm::resume(data, len, std::ptr::null_mut(), land_noop_trampoline)
+            },
             StackImpl::Boxed(ref mut bytes) => {
-                let raw = Box::into_raw(std::mem::take(bytes));
-                let stack_data = raw as *mut u8;
-                let stack_len = raw.len();
+                let mut bytes = std::mem::ManuallyDrop::new(std::mem::take(bytes));
                 unsafe {
                     // Call the underlying assembly function to land the new stack.
                     asm::resume(
-                        stack_data,
-                        stack_len,
-                        // We don't need to pass any context to our no-op callback.
-                        std::ptr::null_mut(),
-                        land_drop_coroutine_trampoline,
+                        bytes.as_mut_ptr(),
+                        bytes.len(),
+                        // The callback needs the capacity to give the buffer back to the pool.
+                        bytes.capacity() as *mut (),
+                        land_recycle_trampoline,
                     );
                 }
             }
@@ -157,21 +535,474 @@ impl Stack {
                 ref mut a,
                 drop_a: _,
             } => unsafe { asm::restart(f, std::mem::take(a)) },
+            StackImpl::Live(_) => unreachable!("live stacks are resumed above"),
+            StackImpl::Chunked { .. }
+            | StackImpl::Compressed { .. }
+            | StackImpl::Spilled { .. } => {
+                unreachable!("spilled, chunked and compressed stacks are loaded above")
+            }
         }
     }
 
-    pub(crate) unsafe fn from_parts_owned(stack_data: *mut u8, stack_len: usize) -> Self {
+    /// pushes the stack to the back of the run queue of the current dock, it will be resumed by [`Stack::run_next`]
+    ///
+    /// ## SAFETY
+    /// it is undefined behaviour to:
+    /// - call this function outside a call to [`Stack::dock`]
+    /// - call this function with a stack suspended from a different call to [`Stack::dock`]
+    pub unsafe fn schedule(stack: Stack) {
+        dock::with(|dock| dock.run_queue.push_back(stack));
+    }
+
+    /// discards the current stack without unwinding or running destructors, and replaces it with the next stack of the run queue
+    ///
+    /// if the run queue is empty but some stacks are parked (see [`Stack::park`]), this blocks the thread until one of them is woken
+    ///
+    /// if nothing is runnable or parked, this calls [`Stack::restart`] with `on_empty` instead
+    ///
+    /// ## SAFETY
+    /// it is undefined behaviour to:
+    /// - call this function outside a call to [`Stack::dock`]
+    /// - for on_empty to unwind
+    /// - call this function with an on_empty that has a output type that is different from the output type of [`Stack::dock`]
+    pub unsafe fn run_next<T>(on_empty: impl FnOnce() -> T + 'static) -> ! {
+        unsafe { dock::switch(|| Stack::restart(on_empty)) }
+    }
+
+    /// suspends the current stack to the back of the run queue and resumes the next one
+    ///
+    /// ## SAFETY
+    /// the same as [`Stack::suspend`]
+    pub unsafe fn yield_now() {
         unsafe {
-            Stack(StackImpl::Boxed(Box::from_raw(
-                std::ptr::slice_from_raw_parts_mut(stack_data, stack_len),
-            )))
+            Stack::suspend(|stack| {
+                dock::with(|dock| dock.run_queue.push_back(stack));
+                dock::switch(|| unreachable!("a stack was just scheduled"))
+            })
+        }
+    }
+
+    /// suspends the current stack until the [`WakeHandle`] passed to the callback is woken, resuming the next stack of the run queue in the meantime
+    ///
+    /// the handle can be sent to other threads, waking it from there interrupts the idle wait of the dock
+    ///
+    /// ## SAFETY
+    /// the same as [`Stack::suspend`]
+    pub unsafe fn park<F>(f: F)
+    where
+        F: FnOnce(WakeHandle) + 'static,
+    {
+        unsafe {
+            Stack::suspend(|stack| {
+                let handle = dock::with(|dock| dock.handle());
+                dock::park(&handle, stack);
+                f(handle);
+                dock::switch(|| unreachable!("a stack was just parked"))
+            })
+        }
+    }
+
+    /// statistics of the buffer pool of the current dock
+    ///
+    /// the buffers of landed stacks are kept by the dock and reused by the next suspensions
+    ///
+    /// panics if called outside a call to [`Stack::dock`]
+    pub fn pool_stats() -> PoolStats {
+        dock::with(|dock| dock.pool.stats())
+    }
+
+    /// statistics of the stack switches of the current dock
+    ///
+    /// this only copies a few counters the dock keeps anyway, so it is cheap enough to be scraped often
+    ///
+    /// panics if called outside a call to [`Stack::dock`]
+    pub fn dock_stats() -> DockStats {
+        dock::with(|dock| dock.stats)
+    }
+
+    /// sets the size up to which stacks suspended in the current dock are stored inline, instead of in a buffer from the pool
+    ///
+    /// the threshold is clamped to [`INLINE_CAPACITY`], which is also the default
+    ///
+    /// panics if called outside a call to [`Stack::dock`]
+    pub fn set_inline_threshold(threshold: usize) {
+        dock::with(|dock| dock.inline_threshold = threshold.min(INLINE_CAPACITY));
+    }
+
+    /// whether resuming the stack that was just suspended in the current dock lands it where it is, off by default
+    ///
+    /// the bytes of every suspended stack are left on the dock until something else lands or restarts, which copies them out first,
+    /// so resuming the same stack right away (like a [`Stack::yield_now`] with an empty run queue) copies nothing
+    ///
+    /// panics if called outside a call to [`Stack::dock`]
+    pub fn set_resume_in_place(enabled: bool) {
+        dock::with(|dock| dock.resume_in_place = enabled);
+    }
+
+    /// compresses the bytes of the stack with `codec`, they are decompressed when the stack is resumed
+    ///
+    /// returns whether the stack is compressed now, stacks that were never suspended, inline stacks, stacks in a buffer from [`Stack::suspend_into`]
+    /// and stacks that would not get any smaller are left as they are
+    pub fn compress(&mut self, codec: &'static dyn Codec) -> bool {
+        self.settle_live();
+        let bytes = match self.stored_bytes() {
+            Some((bytes, None)) => bytes,
+            Some((_, Some(_))) => return true,
+            None => return matches!(self.inner, StackImpl::Spilled { codec: Some(_), .. }),
+        };
+        let mut compressed = Vec::new();
+        codec.compress(bytes, &mut compressed);
+        if compressed.len() >= bytes.len() {
+            return false;
+        }
+        self.inner = StackImpl::Compressed {
+            bytes: compressed.into_boxed_slice(),
+            len: bytes.len(),
+            codec,
+        };
+        self.account();
+        true
+    }
+
+    /// writes the bytes of the stack to `file`, they are read back when the stack is resumed
+    ///
+    /// compressed stacks are written compressed, returns whether the stack is spilled now, the same stacks as in [`Stack::compress`] are left as they are
+    pub fn spill(&mut self, file: &Rc<SpillFile>) -> std::io::Result<bool> {
+        self.settle_live();
+        let Some((bytes, codec)) = self.stored_bytes() else {
+            return Ok(matches!(self.inner, StackImpl::Spilled { .. }));
+        };
+        let stored = bytes.len();
+        let offset = file.write(bytes)?;
+        self.inner = StackImpl::Spilled {
+            file: file.clone(),
+            offset,
+            stored,
+            len: self.len(),
+            codec,
+        };
+        self.account();
+        Ok(true)
+    }
+
+    /// compresses every stack parked in the current dock that has at least `threshold` bytes with `codec`, or stops compressing them if `codec` is `None`
+    ///
+    /// parked stacks are the ones that may stay suspended for a long time, see [`Stack::compress`]
+    ///
+    /// panics if called outside a call to [`Stack::dock`]
+    pub fn set_compression(codec: Option<&'static dyn Codec>, threshold: usize) {
+        dock::with(|dock| dock.compression = codec.map(|codec| (codec, threshold)));
+    }
+
+    /// spills the stacks parked in the current dock to `file`, oldest first, while the ones in memory take more than `budget` bytes, or stops spilling them if `file` is `None`
+    ///
+    /// spilled stacks are read back when they are resumed, see [`Stack::spill`]
+    ///
+    /// panics if called outside a call to [`Stack::dock`]
+    pub fn set_spill_budget(file: Option<Rc<SpillFile>>, budget: usize) {
+        dock::with(|dock| dock.spill = file.map(|file| (file, budget)));
+        dock::enforce_spill_budget();
+    }
+
+    /// makes the stacks suspended in the current dock be stored as chunks, which are shared between every stack that has the same bytes at the same distance from the dock
+    ///
+    /// coroutines spawned from the same entry have the same bottom frames, so those are stored once,
+    /// stacks small enough to be inline (see [`Stack::set_inline_threshold`]) and stacks on a region with a write watch are not affected
+    ///
+    /// panics if called outside a call to [`Stack::dock`]
+    pub fn set_deduplication(enabled: bool) {
+        dock::with(|dock| dock.chunks = enabled.then(chunk::ChunkStore::default));
+    }
+
+    /// statistics of the deduplication of the current dock, since it was last enabled
+    ///
+    /// panics if called outside a call to [`Stack::dock`]
+    pub fn chunk_stats() -> ChunkStats {
+        dock::with(|dock| {
+            dock.chunks
+                .as_ref()
+                .map_or_else(ChunkStats::default, |chunks| chunks.stats())
+        })
+    }
+
+    /// limits the bytes held by the stacks suspended in the current dock to `limit`
+    ///
+    /// when a suspension would go past it, `policy` is called with the stack being suspended to decide what to do, see [`Pressure`]
+    ///
+    /// panics if called outside a call to [`Stack::dock`]
+    pub fn set_memory_budget(limit: usize, policy: impl FnMut(&mut Stack) -> Pressure + 'static) {
+        dock::with(|dock| {
+            dock.budget = Some(budget::Budget {
+                limit,
+                policy: Some(Box::new(policy)),
+            });
+            dock.shared.unblock();
+        });
+    }
+
+    /// removes the memory budget of the current dock, unblocking anyone waiting on it
+    ///
+    /// panics if called outside a call to [`Stack::dock`]
+    pub fn clear_memory_budget() {
+        dock::with(|dock| {
+            dock.budget = None;
+            dock.shared.unblock();
+        });
+    }
+
+    /// the bytes of memory held by the stacks suspended in the current dock
+    ///
+    /// stacks in a buffer from [`Stack::suspend_into`], spilled stacks and inline stacks do not hold any, see [`Stack::set_memory_budget`]
+    ///
+    /// panics if called outside a call to [`Stack::dock`]
+    pub fn suspended_bytes() -> usize {
+        dock::with(|dock| dock.suspended_bytes)
+    }
+
+    /// the bytes of the stack that are kept in memory, and the codec they were compressed with
+    ///
+    /// `None` for stacks that cannot be compressed or spilled, see [`Stack::compress`]
+    fn stored_bytes(&self) -> Option<(&[u8], Option<&'static dyn Codec>)> {
+        match self.inner {
+            StackImpl::Boxed(ref bytes) => Some((bytes, None)),
+            StackImpl::Tracked { ref bytes, len } => Some((&bytes[bytes.len() - len..], None)),
+            StackImpl::Live(ref live) => {
+                // the copy is only set while there is none, and only taken through an owned or mutable stack, so it stays put while this one is borrowed
+                match unsafe { live.copy.try_borrow_unguarded() }.ok()? {
+                    Some(copy) => copy.stored_bytes(),
+                    // the bytes are still on the dock, they can be read from there
+                    None => Some((
+                        unsafe { std::slice::from_raw_parts(live.data, live.len) },
+                        None,
+                    )),
+                }
+            }
+            StackImpl::Compressed {
+                ref bytes, codec, ..
+            } => Some((bytes, Some(codec))),
+            _ => None,
+        }
+    }
+
+    /// the bytes of the stack that take memory, the ones that spilling it would free
+    pub(crate) fn resident_len(&self) -> usize {
+        self.stored_bytes().map_or(0, |(bytes, _)| bytes.len())
+    }
+
+    /// the bytes of memory the stack holds, which count against the memory budget of its dock
+    ///
+    /// chunks are counted by every stack that has them, live stacks are counted by their copy once they have one
+    fn held_bytes(&self) -> usize {
+        match self.inner {
+            StackImpl::Boxed(ref bytes) => bytes.len(),
+            StackImpl::Tracked { ref bytes, .. } | StackImpl::Compressed { ref bytes, .. } => {
+                bytes.len()
+            }
+            StackImpl::Chunked { len, .. } => len,
+            StackImpl::Inline { .. }
+            | StackImpl::Live(_)
+            | StackImpl::Spilled { .. }
+            | StackImpl::External { .. }
+            | StackImpl::Empty { .. } => 0,
+        }
+    }
+
+    /// brings the suspended bytes of the current dock up to date with the bytes the stack holds
+    pub(crate) fn account(&mut self) {
+        let held = self.held_bytes();
+        let accounted = std::mem::replace(&mut self.accounted, held);
+        if accounted != held {
+            dock::try_with(|dock| dock.account(accounted, held));
+        }
+    }
+
+    /// forgets the bytes counted in the dock without taking them off, for when the dock is gone, the copy of a live stack included
+    pub(crate) fn forget_accounted(&mut self) {
+        self.accounted = 0;
+        if let StackImpl::Live(ref live) = self.inner
+            && let Some(copy) = live.copy.borrow_mut().as_mut()
+        {
+            copy.accounted = 0;
+        }
+    }
+
+    /// stops counting the bytes of the stack in the suspended bytes of the current dock
+    ///
+    /// the bytes are always counted in the current dock, so a stack that moves to another dock is taken off here first, see [`SendStack`]
+    pub(crate) fn unaccount(&mut self) {
+        let accounted = std::mem::take(&mut self.accounted);
+        if accounted != 0 {
+            dock::try_with(|dock| dock.account(accounted, 0));
+        }
+    }
+
+    /// reads the bytes of a spilled stack back into memory, they stay compressed if they were written compressed
+    ///
+    /// the bytes held by the stack change, it is up to the caller to account for them
+    pub(crate) fn load(&mut self) -> std::io::Result<()> {
+        if let StackImpl::Spilled {
+            ref file,
+            offset,
+            stored,
+            len,
+            codec,
+        } = self.inner
+        {
+            let mut bytes = match codec {
+                Some(_) => Vec::with_capacity(stored),
+                None => pool::take(len),
+            };
+            file.read(offset, stored, &mut bytes)?;
+            self.inner = match codec {
+                Some(codec) => StackImpl::Compressed {
+                    bytes: bytes.into_boxed_slice(),
+                    len,
+                    codec,
+                },
+                None => StackImpl::Boxed(bytes),
+            };
+        }
+        Ok(())
+    }
+
+    /// replaces chunks with a buffer of their own, so that nothing is shared with the other stacks of the dock anymore
+    ///
+    /// the bytes held by the stack change, it is up to the caller to account for them
+    pub(crate) fn unshare(&mut self) {
+        if let StackImpl::Chunked { ref chunks, len } = self.inner {
+            let mut bytes = pool::take(len);
+            chunk::ChunkStore::join(chunks, &mut bytes);
+            self.inner = StackImpl::Boxed(bytes);
         }
     }
+
+    /// replaces compressed bytes with the bytes they were compressed from
+    ///
+    /// the bytes held by the stack change, it is up to the caller to account for them
+    pub(crate) fn decompress(&mut self) {
+        if let StackImpl::Compressed {
+            ref mut bytes,
+            len,
+            codec,
+        } = self.inner
+        {
+            let compressed = std::mem::take(bytes);
+            let mut bytes = pool::take(len);
+            codec.decompress(&compressed, &mut bytes);
+            debug_assert_eq!(bytes.len(), len, "the codec did not give back every byte");
+            self.inner = StackImpl::Boxed(bytes);
+        }
+    }
+
+    /// if the stack is live and was already copied out, replaces it with the copy
+    fn settle_live(&mut self) {
+        if let StackImpl::Live(ref live) = self.inner {
+            let copy = live.copy.borrow_mut().take();
+            if let Some(mut copy) = copy {
+                copy.id = self.id.take();
+                *self = copy;
+            }
+        }
+    }
+
+    /// the same as [`Stack::from_parts_copied`], but the bytes are left on the dock until something is about to overwrite them
+    pub(crate) unsafe fn from_parts_live(stack_data: *const u8, stack_len: usize) -> Self {
+        match live::LiveStack::new(stack_data, stack_len) {
+            Some(live) => Stack {
+                inner: StackImpl::Live(live),
+                start: unsafe { stack_data.add(stack_len) },
+                // the bytes are on the dock, once copied out the copy counts them
+                accounted: 0,
+                id: None,
+            },
+            None => unsafe { Stack::from_parts_copied(stack_data, stack_len) },
+        }
+    }
+
+    /// makes sure the bytes of the stack are not on the dock anymore
+    pub(crate) fn into_owned(mut self) -> Stack {
+        match self.inner {
+            StackImpl::Live(ref live) => {
+                live.materialize();
+                let mut copy = live
+                    .copy
+                    .borrow_mut()
+                    .take()
+                    .expect("the stack was just copied out");
+                copy.id = self.id.take();
+                copy
+            }
+            _ => self,
+        }
+    }
+
+    /// the stack data ends where the dock starts, so that is where it must land again
     pub(crate) unsafe fn from_parts_copied(stack_data: *const u8, stack_len: usize) -> Self {
+        let timer = trace::CopyTimer::start();
+        let tracked = dock::try_with(|dock| {
+            let watch = dock.watch.as_mut()?;
+            Some(unsafe { watch.capture(stack_data, stack_len) })
+        });
+        let mut stack = if let Some(Some(bytes)) = tracked {
+            Stack {
+                inner: StackImpl::Tracked {
+                    bytes,
+                    len: stack_len,
+                },
+                start: unsafe { stack_data.add(stack_len) },
+                accounted: 0,
+                id: None,
+            }
+        } else {
+            unsafe { Stack::from_parts_untracked(stack_data, stack_len) }
+        };
+        stack.account();
+        trace::copied_out(stack_len, timer);
+        stack
+    }
+
+    /// the same as [`Stack::from_parts_copied`], without the write watch
+    unsafe fn from_parts_untracked(stack_data: *const u8, stack_len: usize) -> Self {
+        let threshold = dock::try_with(|dock| dock.inline_threshold).unwrap_or(INLINE_CAPACITY);
         unsafe {
-            Stack(StackImpl::Boxed(Box::from(std::slice::from_raw_parts(
-                stack_data, stack_len,
-            ))))
+            Stack {
+                inner: if stack_len <= threshold {
+                    let mut bytes = [std::mem::MaybeUninit::uninit(); INLINE_CAPACITY];
+                    std::ptr::copy_nonoverlapping(
+                        stack_data,
+                        bytes.as_mut_ptr() as *mut u8,
+                        stack_len,
+                    );
+                    StackImpl::Inline {
+                        len: stack_len,
+                        bytes,
+                    }
+                } else if let Some(Some(chunks)) = dock::try_with(|dock| {
+                    let bytes = std::slice::from_raw_parts(stack_data, stack_len);
+                    Some(dock.chunks.as_mut()?.split(bytes))
+                }) {
+                    StackImpl::Chunked {
+                        chunks,
+                        len: stack_len,
+                    }
+                } else {
+                    let mut bytes = pool::take(stack_len);
+                    bytes.extend_from_slice(std::slice::from_raw_parts(stack_data, stack_len));
+                    StackImpl::Boxed(bytes)
+                },
+                start: stack_data.add(stack_len),
+                accounted: 0,
+                id: None,
+            }
+        }
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        self.unaccount();
+        if let Some(id) = self.id {
+            registry::remove(id);
         }
     }
 }
@@ -179,7 +1010,19 @@ impl Stack {
 impl Drop for StackImpl {
     fn drop(&mut self) {
         match *self {
-            StackImpl::Boxed(_) => {}
+            StackImpl::Boxed(ref mut bytes) => pool::recycle(std::mem::take(bytes)),
+            StackImpl::Spilled {
+                ref file,
+                offset,
+                stored,
+                ..
+            } => file.free(offset, stored),
+            StackImpl::Inline { .. }
+            | StackImpl::Live(_)
+            | StackImpl::Chunked { .. }
+            | StackImpl::Compressed { .. }
+            | StackImpl::Tracked { .. }
+            | StackImpl::External { .. } => {}
             StackImpl::Empty { f: _, a, drop_a } => unsafe {
                 if !a.is_null() {
                     drop_a(a);
@@ -193,7 +1036,11 @@ unsafe extern "stdcall" fn boxed_entry<F, T>(entry: *mut F) -> *mut T
 where
     F: FnOnce() -> T,
 {
-    unsafe { Box::into_raw(Box::new(Box::from_raw(entry)())) }
+    asan::arrived(std::ptr::null_mut());
+    trace::started();
+    let result = unsafe { Box::into_raw(Box::new(Box::from_raw(entry)())) };
+    asan::returning_from_dock();
+    result
 }
 
 unsafe fn boxed_drop<T>(entry: *mut ()) {
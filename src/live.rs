@@ -0,0 +1,60 @@
+//! stacks whose bytes were left on the dock, so resuming the stack that was just suspended copies nothing
+
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+use crate::{Stack, dock};
+
+/// the bytes of a suspended stack, still on the dock
+///
+/// they stay there until something else lands or restarts, which copies them out first, see [`materialize`]
+pub(crate) struct LiveStack {
+    pub(crate) data: *const u8,
+    pub(crate) len: usize,
+    /// the copy of the bytes, once they had to leave the dock
+    pub(crate) copy: RefCell<Option<Stack>>,
+}
+
+impl LiveStack {
+    /// leaves the bytes on the dock, the current dock will copy them out before they are overwritten
+    ///
+    /// returns `None` outside of a dock, since then there is nobody to copy them out, and in docks that do not resume in place (see [`Stack::set_resume_in_place`])
+    pub(crate) fn new(data: *const u8, len: usize) -> Option<Rc<LiveStack>> {
+        let live = Rc::new(LiveStack {
+            data,
+            len,
+            copy: RefCell::new(None),
+        });
+        dock::try_with(|dock| {
+            if dock.resume_in_place {
+                dock.live = Rc::downgrade(&live);
+            }
+            dock.resume_in_place
+        })?
+        .then_some(live)
+    }
+
+    /// copies the bytes out of the dock, if that was not done yet
+    pub(crate) fn materialize(&self) {
+        let mut copy = self.copy.borrow_mut();
+        if copy.is_none() {
+            *copy = Some(unsafe { Stack::from_parts_copied(self.data, self.len) });
+        }
+    }
+
+    /// whether the bytes are still on the dock of the current thread
+    pub(crate) fn is_on_dock(self: &Rc<Self>) -> bool {
+        self.copy.borrow().is_none()
+            && dock::try_with(|dock| Weak::ptr_eq(&dock.live, &Rc::downgrade(self)))
+                .unwrap_or(false)
+    }
+}
+
+/// copies out the live stack of the current dock, if it has one
+///
+/// must be called before anything lands or restarts
+pub(crate) fn materialize() {
+    if let Some(live) = dock::try_with(|dock| std::mem::take(&mut dock.live).upgrade()).flatten() {
+        live.materialize();
+    }
+}
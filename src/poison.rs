@@ -0,0 +1,138 @@
+//! catching pointers to the locals of a coroutine that outlive its suspension
+//!
+//! once a stack is suspended its bytes are somewhere else, and whatever lands next overwrites the memory the locals were at,
+//! so a pointer to one of them that someone else held on to reads garbage (or worse, writes into the frames of another coroutine)
+//!
+//! see [`Stack::set_poison`](crate::Stack::set_poison) and [`Stack::scan_for_escapes`](crate::Stack::scan_for_escapes)
+
+use std::collections::BTreeMap;
+
+use crate::{CoroutineId, Stack, dock, live};
+
+/// a pointer into the stack of a coroutine that was found in a registered object after the coroutine was suspended, see [`Stack::scan_for_escapes`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Escape {
+    /// the coroutine that was suspended
+    pub coroutine: Option<CoroutineId>,
+    /// the start of the registered object the pointer was found in
+    pub object: *const u8,
+    /// where the pointer is in the object
+    pub offset: usize,
+    /// the pointer itself, it points into the bytes the coroutine left on the dock
+    pub pointer: *const u8,
+}
+
+impl std::fmt::Display for Escape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} at offset {} of the object at {:?} points into the stack of the suspended coroutine",
+            self.pointer, self.offset, self.object
+        )?;
+        if let Some(coroutine) = self.coroutine {
+            write!(f, " {coroutine}")?;
+        }
+        Ok(())
+    }
+}
+
+/// keeps an object registered with [`Stack::scan_for_escapes`], dropping it unregisters the object
+#[derive(Debug)]
+pub struct ScannedObject {
+    id: u64,
+}
+
+impl Drop for ScannedObject {
+    fn drop(&mut self) {
+        dock::try_with(|dock| dock.scanner.objects.remove(&self.id));
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Scanner {
+    /// the registered objects, as `(start, len)`
+    objects: BTreeMap<u64, (*const u8, usize)>,
+    next_id: u64,
+    escapes: Vec<Escape>,
+}
+
+impl Scanner {
+    /// looks for pointers to the `len` bytes at `data` in every registered object
+    fn scan(&mut self, data: *const u8, len: usize, coroutine: Option<CoroutineId>) {
+        let range = data as usize..data as usize + len;
+        for &(object, object_len) in self.objects.values() {
+            let align = object.align_offset(align_of::<usize>());
+            for offset in (align..object_len.saturating_sub(size_of::<usize>() - 1))
+                .step_by(size_of::<usize>())
+            {
+                let pointer = unsafe { object.add(offset).cast::<usize>().read() };
+                if range.contains(&pointer) {
+                    self.escapes.push(Escape {
+                        coroutine,
+                        object,
+                        offset,
+                        pointer: pointer as *const u8,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// the `len` bytes at `data` were left behind by a coroutine that was suspended and will not be read again, called after the suspension can no longer be rejected
+pub(crate) fn vacated(data: *mut u8, len: usize, coroutine: Option<CoroutineId>) {
+    let Some(poison) = dock::try_with(|dock| {
+        dock.scanner.scan(data, len, coroutine);
+        dock.poison
+    }) else {
+        return;
+    };
+    if poison {
+        // the bytes of the stack may still be on the dock, and the stack would be resumed from them
+        live::materialize();
+        unsafe { data.write_bytes(Stack::POISON, len) };
+    }
+}
+
+impl Stack {
+    /// the byte [`Stack::set_poison`] fills the memory suspended stacks leave behind with
+    ///
+    /// a pointer made of it is in kernel space, so dereferencing one is an access violation
+    pub const POISON: u8 = 0xDD;
+
+    /// whether to fill the memory a stack leaves behind on the dock with [`Stack::POISON`] once it is suspended, off by default
+    ///
+    /// this is a debugging aid, a pointer to a local of a suspended coroutine reads the poison instead of what happens to be there,
+    /// it makes every suspension copy the stack out of the dock, even if it is resumed again right away
+    ///
+    /// panics if called outside a call to [`Stack::dock`]
+    pub fn set_poison(enabled: bool) {
+        dock::with(|dock| dock.poison = enabled);
+    }
+
+    /// registers the `len` bytes at `data` to be scanned for pointers into every stack that is suspended in the current dock, until the returned value is dropped
+    ///
+    /// the pointers found are collected, see [`Stack::take_escapes`]
+    ///
+    /// panics if called outside a call to [`Stack::dock`]
+    ///
+    /// ## SAFETY
+    /// it is undefined behaviour to:
+    /// - register memory that is not readable for `len` bytes starting at `data`
+    /// - free the memory while it is registered
+    pub unsafe fn scan_for_escapes(data: *const u8, len: usize) -> ScannedObject {
+        dock::with(|dock| {
+            let id = dock.scanner.next_id;
+            dock.scanner.next_id += 1;
+            dock.scanner.objects.insert(id, (data, len));
+            ScannedObject { id }
+        })
+    }
+
+    /// the pointers into suspended stacks found since the last call, see [`Stack::scan_for_escapes`]
+    ///
+    /// panics if called outside a call to [`Stack::dock`]
+    pub fn take_escapes() -> Vec<Escape> {
+        dock::with(|dock| std::mem::take(&mut dock.scanner.escapes))
+    }
+}
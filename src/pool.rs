@@ -0,0 +1,96 @@
+//! a per-dock pool of the buffers that hold suspended stacks, so switching does not have to go through the allocator
+
+use crate::dock;
+
+/// the capacity of the buffers of the smallest size class, every class doubles it
+const MIN_CLASS_SIZE: usize = 256;
+/// stacks of up to `MIN_CLASS_SIZE << (CLASSES - 1)` bytes (8 MiB) get pooled buffers
+const CLASSES: usize = 16;
+/// the most free buffers the pool keeps of a single class
+const MAX_PER_CLASS: usize = 64;
+
+#[derive(Default)]
+pub(crate) struct Pool {
+    /// free buffers by size class, the buffers of class `i` have a capacity of `MIN_CLASS_SIZE << i`
+    classes: [Vec<Vec<u8>>; CLASSES],
+    stats: PoolStats,
+}
+
+/// statistics of the buffer pool of a dock, see [`Stack::pool_stats`](crate::Stack::pool_stats)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// buffers taken from the pool
+    pub hits: u64,
+    /// buffers that had to be allocated because the pool had none of the right class
+    pub misses: u64,
+    /// buffers given back to the pool
+    pub recycled: u64,
+    /// buffers freed instead of given back, because their class was full or they were too big
+    pub discarded: u64,
+    /// free buffers the pool is holding right now
+    pub pooled_buffers: usize,
+    /// the total capacity of the free buffers the pool is holding right now
+    pub pooled_bytes: usize,
+}
+
+/// the size class of a buffer that can hold `len` bytes, `None` if it is too big to be pooled
+fn class_of(len: usize) -> Option<usize> {
+    let class =
+        (len.max(MIN_CLASS_SIZE).next_power_of_two() / MIN_CLASS_SIZE).trailing_zeros() as usize;
+    (class < CLASSES).then_some(class)
+}
+
+impl Pool {
+    /// an empty buffer with a capacity of at least `len`
+    fn take(&mut self, len: usize) -> Vec<u8> {
+        let Some(class) = class_of(len) else {
+            self.stats.misses += 1;
+            return Vec::with_capacity(len);
+        };
+        match self.classes[class].pop() {
+            Some(bytes) => {
+                self.stats.hits += 1;
+                self.stats.pooled_buffers -= 1;
+                self.stats.pooled_bytes -= bytes.capacity();
+                bytes
+            }
+            None => {
+                self.stats.misses += 1;
+                Vec::with_capacity(MIN_CLASS_SIZE << class)
+            }
+        }
+    }
+
+    fn recycle(&mut self, mut bytes: Vec<u8>) {
+        let capacity = bytes.capacity();
+        match class_of(capacity) {
+            Some(class)
+                if MIN_CLASS_SIZE << class == capacity
+                    && self.classes[class].len() < MAX_PER_CLASS =>
+            {
+                bytes.clear();
+                self.stats.recycled += 1;
+                self.stats.pooled_buffers += 1;
+                self.stats.pooled_bytes += capacity;
+                self.classes[class].push(bytes);
+            }
+            _ => self.stats.discarded += 1,
+        }
+    }
+
+    pub(crate) fn stats(&self) -> PoolStats {
+        self.stats
+    }
+}
+
+/// an empty buffer with a capacity of at least `len`, from the pool of the current dock if there is one
+pub(crate) fn take(len: usize) -> Vec<u8> {
+    dock::try_with(|dock| dock.pool.take(len)).unwrap_or_else(|| Vec::with_capacity(len))
+}
+
+/// gives the buffer to the pool of the current dock, or frees it if there is none
+pub(crate) fn recycle(bytes: Vec<u8>) {
+    if bytes.capacity() != 0 {
+        let _ = dock::try_with(|dock| dock.pool.recycle(bytes));
+    }
+}
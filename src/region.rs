@@ -0,0 +1,152 @@
+//! memory that can be docked on instead of the stack of the current thread
+
+use std::io;
+use std::ptr::NonNull;
+
+use crate::sys;
+
+/// a block of memory used as the stack of a dock, see [`Stack::dock_on`](crate::Stack::dock_on)
+///
+/// a dock on a region always starts at [`Region::top`], so stacks suspended from it land at a predictable address,
+/// and can be resumed by a later dock on the same region
+pub struct Region {
+    base: NonNull<u8>,
+    size: usize,
+    /// the number of bytes at the bottom of the region that the stack must not grow into
+    guard: usize,
+    /// whether the region was allocated by [`Region::new`], and must be freed
+    owned: bool,
+    /// whether the region was allocated by [`Region::with_write_watch`]
+    write_watch: bool,
+}
+
+impl Region {
+    /// the size of a page, regions allocated by [`Region::new`] are made of whole pages
+    pub const PAGE_SIZE: usize = 4096;
+
+    /// allocates a region with at least `size` usable bytes, plus a guard page below them
+    ///
+    /// growing the stack into the guard page is an access violation instead of a silent corruption of whatever is below the region
+    pub fn new(size: usize) -> io::Result<Self> {
+        Self::allocate(size, false)
+    }
+
+    /// the same as [`Region::new`], but the region also tracks which of its pages are written to
+    ///
+    /// docks on such a region only copy the pages that were written to since a stack landed when suspending that stack again,
+    /// the rest is reused from the buffer the stack landed from
+    pub fn with_write_watch(size: usize) -> io::Result<Self> {
+        Self::allocate(size, true)
+    }
+
+    fn allocate(size: usize, write_watch: bool) -> io::Result<Self> {
+        let size = size.max(1).next_multiple_of(Self::PAGE_SIZE) + Self::PAGE_SIZE;
+        let mut allocation_type = sys::MEM_RESERVE | sys::MEM_COMMIT;
+        if write_watch {
+            allocation_type |= sys::MEM_WRITE_WATCH;
+        }
+        unsafe {
+            let base = sys::VirtualAlloc(
+                std::ptr::null_mut(),
+                size,
+                allocation_type,
+                sys::PAGE_READWRITE,
+            );
+            let Some(base) = NonNull::new(base as *mut u8) else {
+                return Err(io::Error::last_os_error());
+            };
+            let region = Region {
+                base,
+                size,
+                guard: Self::PAGE_SIZE,
+                owned: true,
+                write_watch,
+            };
+            let mut old = 0;
+            if sys::VirtualProtect(
+                base.as_ptr() as _,
+                Self::PAGE_SIZE,
+                sys::PAGE_NOACCESS,
+                &mut old,
+            ) == 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(region)
+        }
+    }
+
+    /// uses memory provided by the caller as a region, it is not freed when the region is dropped
+    ///
+    /// no guard page is added, the whole memory is usable by the stack, except for the bytes at the top that are cut off to align it
+    ///
+    /// fails with [`io::ErrorKind::InvalidInput`] if nothing is left once aligned
+    ///
+    /// ## SAFETY
+    /// it is undefined behaviour to:
+    /// - pass memory that is not readable and writable for `size` bytes starting at `base`
+    /// - use the memory for anything else while the region is alive
+    pub unsafe fn from_raw(base: NonNull<u8>, size: usize) -> io::Result<Self> {
+        // keep the top 16 byte aligned, like the stack of a thread
+        let top = (base.as_ptr() as usize).saturating_add(size) & !15;
+        let Some(size) = top
+            .checked_sub(base.as_ptr() as usize)
+            .filter(|&size| size > 0)
+        else {
+            return Err(io::ErrorKind::InvalidInput.into());
+        };
+        Ok(Region {
+            base,
+            size,
+            guard: 0,
+            owned: false,
+            write_watch: false,
+        })
+    }
+
+    /// the lowest address the stack can use
+    pub fn limit(&self) -> *mut u8 {
+        unsafe { self.base.as_ptr().add(self.guard) }
+    }
+
+    /// one past the highest address of the region, this is where the stack of a dock on this region starts
+    pub fn top(&self) -> *mut u8 {
+        unsafe { self.base.as_ptr().add(self.size) }
+    }
+
+    /// the number of bytes the stack can use
+    pub fn len(&self) -> usize {
+        self.size - self.guard
+    }
+
+    pub(crate) fn has_write_watch(&self) -> bool {
+        self.write_watch
+    }
+
+    /// whether the stack can not use any bytes of the region
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// a region is just memory, which thread uses it is up to the safety contract of the dock
+unsafe impl Send for Region {}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe {
+                sys::VirtualFree(self.base.as_ptr() as _, 0, sys::MEM_RELEASE);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Region")
+            .field("limit", &self.limit())
+            .field("top", &self.top())
+            .finish()
+    }
+}
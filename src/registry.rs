@@ -0,0 +1,237 @@
+//! ids, names and states of the coroutines of a dock, so that they can be listed while the program runs
+//!
+//! see [`Stack::coroutines`](crate::Stack::coroutines) and [`Stack::dump_coroutines`](crate::Stack::dump_coroutines)
+
+use std::collections::BTreeMap;
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::trace::{self, Spans};
+use crate::{Stack, dock};
+
+/// the id of a coroutine, unique in the whole process
+///
+/// every stack suspended by the coroutine has the same id, see [`Stack::id`](crate::Stack::id)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CoroutineId(u64);
+
+impl std::fmt::Display for CoroutineId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoroutineState {
+    /// created with [`Stack::from_entry`](crate::Stack::from_entry), and not resumed yet
+    NeverStarted,
+    /// this is the coroutine the dock is running
+    Running,
+    Suspended,
+}
+
+/// a coroutine of a dock, see [`Stack::coroutines`](crate::Stack::coroutines)
+#[derive(Clone, Debug)]
+pub struct CoroutineInfo {
+    pub id: CoroutineId,
+    /// set with [`Builder::name`]
+    pub name: Option<String>,
+    pub state: CoroutineState,
+    /// the number of bytes of the stack the last time it was suspended, zero if it never was
+    pub stack_size: usize,
+    /// where the coroutine was created, `None` for coroutines that were not created by [`Stack::from_entry`](crate::Stack::from_entry) (like the entry function of the dock)
+    pub location: Option<&'static Location<'static>>,
+}
+
+/// creates stacks with a name, see [`Stack::builder`](crate::Stack::builder)
+#[derive(Clone, Debug, Default)]
+pub struct Builder {
+    pub(crate) name: Option<String>,
+}
+
+impl Builder {
+    /// the name the coroutine is listed with
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// the same as [`Stack::from_entry`], with the name of the builder
+    ///
+    /// ## SAFETY
+    /// the same as [`Stack::from_entry`]
+    #[track_caller]
+    pub unsafe fn from_entry<F, T>(self, entry: F) -> Stack
+    where
+        F: FnOnce() -> T + 'static,
+    {
+        unsafe { Stack::from_entry_at(entry, self.name, Location::caller()) }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Registry {
+    coroutines: BTreeMap<CoroutineId, CoroutineInfo>,
+    /// the coroutine that is running, `None` if it was never registered
+    current: Option<CoroutineId>,
+    /// the last value of `DUMP_REQUESTS` this dock dumped for
+    dumped: usize,
+    pub(crate) spans: Spans,
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// incremented by [`request_dump`], every dock dumps its coroutines once it sees it changed
+static DUMP_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn new_id() -> CoroutineId {
+    CoroutineId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+}
+
+impl Registry {
+    fn entry(&mut self, id: CoroutineId) -> &mut CoroutineInfo {
+        self.coroutines.entry(id).or_insert(CoroutineInfo {
+            id,
+            name: None,
+            state: CoroutineState::Running,
+            stack_size: 0,
+            location: None,
+        })
+    }
+
+    /// registers a coroutine that was just created
+    pub(crate) fn created(
+        &mut self,
+        id: CoroutineId,
+        name: Option<String>,
+        location: &'static Location<'static>,
+    ) {
+        self.spans.created(id, name.as_deref());
+        let info = self.entry(id);
+        info.name = name;
+        info.state = CoroutineState::NeverStarted;
+        info.location = Some(location);
+    }
+
+    pub(crate) fn list(&self) -> Vec<CoroutineInfo> {
+        self.coroutines.values().cloned().collect()
+    }
+
+    /// whether a dump was requested since the last time this returned true
+    pub(crate) fn take_dump_request(&mut self) -> bool {
+        let requests = DUMP_REQUESTS.load(Ordering::Relaxed);
+        std::mem::replace(&mut self.dumped, requests) != requests
+    }
+}
+
+/// registers a coroutine that was just created in the current dock, if there is one
+pub(crate) fn created(id: CoroutineId, name: Option<String>, location: &'static Location<'static>) {
+    dock::try_with(|dock| dock.registry.created(id, name, location));
+}
+
+/// the current coroutine was suspended into a stack of `len` bytes, returns its id
+///
+/// coroutines that were not registered yet get an id now
+pub(crate) fn suspended(len: usize) -> CoroutineId {
+    dock::try_with(|dock| {
+        let id = dock.registry.current.take().unwrap_or_else(new_id);
+        dock.registry.spans.exit();
+        trace::suspended(id, len);
+        dock.stats.suspended(len);
+        let info = dock.registry.entry(id);
+        info.state = CoroutineState::Suspended;
+        info.stack_size = len;
+        id
+    })
+    .unwrap_or_else(new_id)
+}
+
+/// the dock switches to the coroutine, `None` for an unregistered one (like one restarted with [`Stack::restart`](crate::Stack::restart))
+///
+/// the coroutine that was running did not suspend, so it is gone
+pub(crate) fn switch_to(id: Option<CoroutineId>) {
+    dock::try_with(|dock| {
+        if let Some(finished) = dock.registry.current.take() {
+            dock.registry.coroutines.remove(&finished);
+            dock.registry.spans.remove(finished);
+        }
+        dock.registry.spans.enter(id);
+        if let Some(id) = id {
+            dock.registry.entry(id).state = CoroutineState::Running;
+        }
+        dock.registry.current = id;
+    });
+}
+
+/// the coroutine is gone, because its stack was dropped or left the dock
+///
+/// returns what the current dock knew about it, if it was registered there
+pub(crate) fn remove(id: CoroutineId) -> Option<CoroutineInfo> {
+    dock::try_with(|dock| {
+        dock.registry.spans.remove(id);
+        dock.registry.coroutines.remove(&id)
+    })
+    .flatten()
+}
+
+/// registers a coroutine whose stack arrived from another dock, with what that dock knew about it
+pub(crate) fn arrived(info: CoroutineInfo) {
+    dock::try_with(|dock| {
+        dock.registry.spans.created(info.id, info.name.as_deref());
+        dock.registry.coroutines.insert(info.id, info);
+    });
+}
+
+/// makes every dock dump its coroutines to stderr, the next time it picks a stack from its run queue
+///
+/// that is in [`Stack::run_next`](crate::Stack::run_next), [`Stack::yield_now`](crate::Stack::yield_now), [`Stack::park`](crate::Stack::park) and [`Stack::await_future`](crate::Stack::await_future),
+/// docks that only switch with [`Stack::suspend`](crate::Stack::suspend) and [`Stack::resume`](crate::Stack::resume) never dump, those can call [`Stack::dump_coroutines`](crate::Stack::dump_coroutines) themselves
+///
+/// this only touches an atomic, so it can be called from a signal handler (or a console control handler)
+pub fn request_dump() {
+    DUMP_REQUESTS.fetch_add(1, Ordering::Relaxed);
+}
+
+impl Stack {
+    /// the coroutines registered in the current dock, by id
+    ///
+    /// a coroutine is registered when it is created with [`Stack::from_entry`] or first suspended, and stays until it finishes or its stack is dropped
+    ///
+    /// panics if called outside a call to [`Stack::dock`]
+    pub fn coroutines() -> Vec<CoroutineInfo> {
+        dock::with(|dock| dock.registry.list())
+    }
+
+    /// writes every coroutine of the current dock to `out`, with the backtraces of the ones in its run queue or parked
+    ///
+    /// see [`request_dump`] to make every dock do this from somewhere else
+    ///
+    /// panics if called outside a call to [`Stack::dock`]
+    pub fn dump_coroutines(out: &mut dyn std::io::Write) -> std::io::Result<()> {
+        let (coroutines, mut backtraces) = dock::with(|dock| {
+            let backtraces = dock
+                .run_queue
+                .iter()
+                .chain(dock.parked.values())
+                .filter_map(|stack| Some((stack.id?, stack.backtrace())))
+                .collect::<std::collections::HashMap<_, _>>();
+            (dock.registry.list(), backtraces)
+        });
+        writeln!(out, "{} coroutines:", coroutines.len())?;
+        for info in coroutines {
+            write!(out, "coroutine {}", info.id)?;
+            if let Some(name) = &info.name {
+                write!(out, " {name:?}")?;
+            }
+            write!(out, " {:?}, {} bytes", info.state, info.stack_size)?;
+            if let Some(location) = info.location {
+                write!(out, ", created at {location}")?;
+            }
+            writeln!(out)?;
+            if let Some(backtrace) = backtraces.remove(&info.id) {
+                write!(out, "{backtrace}")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+//! moving suspended stacks between threads, by having the threads take turns docking on the same region
+
+use std::io;
+use std::sync::{Mutex, PoisonError};
+
+use crate::region::Region;
+use crate::{CoroutineInfo, Stack, asm, registry};
+
+/// a region that many threads can dock on, one at a time
+///
+/// every dock on it starts at the same address, no matter the thread, so a stack suspended in one of them can be resumed in any other, see [`SendStack`]
+pub struct SharedRegion {
+    region: Mutex<Region>,
+    top: usize,
+}
+
+impl SharedRegion {
+    /// allocates a region with at least `size` usable bytes, see [`Region::new`]
+    pub fn new(size: usize) -> io::Result<Self> {
+        Ok(Self::from_region(Region::new(size)?))
+    }
+
+    /// shares a region that was already allocated
+    pub fn from_region(region: Region) -> Self {
+        SharedRegion {
+            top: region.top() as usize,
+            region: Mutex::new(region),
+        }
+    }
+
+    /// one past the highest address of the region, see [`Region::top`]
+    pub fn top(&self) -> *mut u8 {
+        self.top as *mut u8
+    }
+
+    /// docks on the region, see [`Stack::dock_on`]
+    ///
+    /// if another thread is docked on the region this blocks until it leaves
+    ///
+    /// ## SAFETY
+    /// it is undefined behaviour to:
+    /// - call this function inside a call to [`Stack::dock`]
+    /// - use more stack than [`Region::len`] while docked
+    pub unsafe fn dock<T>(&self, entry: impl FnOnce() -> T + 'static) -> Box<T> {
+        let region = self.region.lock().unwrap_or_else(PoisonError::into_inner);
+        unsafe { Stack::dock_on(&region, entry) }
+    }
+}
+
+/// a [`Stack`] that can be sent to other threads
+///
+/// it can only be turned back into a [`Stack`] inside a dock that starts at the same address as the dock it was suspended from,
+/// which for different threads means docks on the same [`SharedRegion`]
+pub struct SendStack {
+    stack: Stack,
+    /// the name and location of the coroutine, registered again by the dock that gets the stack back
+    info: Option<CoroutineInfo>,
+}
+
+// the safety contract of `SendStack::new` makes this sound
+unsafe impl Send for SendStack {}
+
+impl SendStack {
+    /// the bytes of a spilled stack are read back, deduplicated ones are copied out of their chunks and compressed ones are decompressed,
+    /// since the file, the chunks and the codec belong to this thread, if they cannot be read back this returns the error together with the stack
+    ///
+    /// ## SAFETY
+    /// it is undefined behaviour to create a send stack from a stack whose frames or entry function hold values that can not be sent to other threads,
+    /// like an [`Rc`](std::rc::Rc) or a reference to a thread local
+    #[allow(clippy::result_large_err)]
+    pub unsafe fn new(stack: Stack) -> Result<Self, (Stack, io::Error)> {
+        // the bytes of a stack that was just suspended are still on the dock of this thread
+        let mut stack = stack.into_owned();
+        if let Err(error) = stack.load() {
+            return Err((stack, error));
+        }
+        stack.unshare();
+        stack.decompress();
+        // the bytes leave the budget of this dock, the dock that gets the stack back counts them
+        stack.unaccount();
+        // the coroutine is leaving the dock, it is registered again by the dock that gets it back
+        let info = stack.id().and_then(registry::remove);
+        Ok(SendStack { stack, info })
+    }
+
+    /// the address of the dock the stack was suspended from, null if it can be resumed on any dock
+    pub fn landing_address(&self) -> *const u8 {
+        self.stack.landing_address()
+    }
+
+    /// gets the stack back, if it can land on the dock of the current thread, and registers the coroutine there (see [`Stack::coroutines`])
+    ///
+    /// a stack that never ran can land anywhere, so it is always given back, even outside a dock,
+    /// any other stack is only given back inside a dock that starts at its [`SendStack::landing_address`], otherwise this returns the send stack back
+    #[allow(clippy::result_large_err)]
+    pub fn into_stack(self) -> Result<Stack, SendStack> {
+        let current = asm::current_stack_start();
+        if !self.stack.start.is_null() && (current.is_null() || self.stack.start != current) {
+            return Err(self);
+        }
+        if let Some(info) = self.info {
+            registry::arrived(info);
+        }
+        let mut stack = self.stack;
+        stack.account();
+        Ok(stack)
+    }
+}
+
+impl std::fmt::Debug for SendStack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SendStack")
+            .field("landing_address", &self.landing_address())
+            .finish()
+    }
+}
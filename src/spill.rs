@@ -0,0 +1,147 @@
+//! a file that suspended stacks are written to while they are parked, so that they take no memory
+//!
+//! see [`Stack::spill`](crate::Stack::spill) and [`Stack::set_spill_budget`](crate::Stack::set_spill_budget)
+
+use std::cell::{Cell, RefCell};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::windows::fs::{FileExt, OpenOptionsExt};
+use std::path::Path;
+
+use crate::sys;
+
+/// a file that holds the bytes of spilled stacks
+///
+/// the space of a stack is reused by the next stacks once it is read back or dropped
+pub struct SpillFile {
+    file: File,
+    /// the extents that were freed, as `(offset, len)`
+    free: RefCell<Vec<(u64, usize)>>,
+    /// where the next extent goes if none of the free ones are big enough
+    end: Cell<u64>,
+}
+
+impl SpillFile {
+    /// spills into `file`, which must be opened for reading and writing
+    ///
+    /// whatever the file holds is overwritten
+    pub fn new(file: File) -> Self {
+        SpillFile {
+            file,
+            free: RefCell::new(Vec::new()),
+            end: Cell::new(0),
+        }
+    }
+
+    /// spills into a new file in `dir`, the file is deleted once closed
+    ///
+    /// on a ram backed file system (like a ram disk) this is the same as an anonymous tmpfs file
+    pub fn create_in(dir: impl AsRef<Path>) -> io::Result<Self> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = dir.as_ref().join(format!(
+            "stack-master-{}-{}.spill",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .access_mode(sys::GENERIC_READ | sys::GENERIC_WRITE | sys::DELETE)
+            .custom_flags(sys::FILE_FLAG_DELETE_ON_CLOSE)
+            .open(path)?;
+        Ok(SpillFile::new(file))
+    }
+
+    /// the same as [`SpillFile::create_in`] with the temporary directory
+    pub fn temp() -> io::Result<Self> {
+        SpillFile::create_in(std::env::temp_dir())
+    }
+
+    /// the number of bytes the file uses, including the extents that are free
+    pub fn len(&self) -> u64 {
+        self.end.get()
+    }
+
+    /// whether nothing was ever spilled into the file
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// writes the bytes to a free extent, returning its offset
+    pub(crate) fn write(&self, mut bytes: &[u8]) -> io::Result<u64> {
+        let offset = self.allocate(bytes.len());
+        let mut at = offset;
+        let result = (|| {
+            while !bytes.is_empty() {
+                let written = self.file.seek_write(bytes, at)?;
+                if written == 0 {
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+                bytes = &bytes[written..];
+                at += written as u64;
+            }
+            Ok(())
+        })();
+        match result {
+            Ok(()) => Ok(offset),
+            Err(error) => {
+                self.free(offset, (at - offset) as usize + bytes.len());
+                Err(error)
+            }
+        }
+    }
+
+    /// appends the `len` bytes written at `offset` to `out`
+    pub(crate) fn read(&self, offset: u64, len: usize, out: &mut Vec<u8>) -> io::Result<()> {
+        let start = out.len();
+        out.resize(start + len, 0);
+        let mut filled = start;
+        while filled < out.len() {
+            let read = self
+                .file
+                .seek_read(&mut out[filled..], offset + (filled - start) as u64)?;
+            if read == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            filled += read;
+        }
+        Ok(())
+    }
+
+    /// lets the extent be reused
+    pub(crate) fn free(&self, offset: u64, len: usize) {
+        if len != 0 {
+            self.free.borrow_mut().push((offset, len));
+        }
+    }
+
+    /// the offset of a free extent of `len` bytes, the first one that is big enough
+    fn allocate(&self, len: usize) -> u64 {
+        let mut free = self.free.borrow_mut();
+        match free.iter().position(|&(_, free)| free >= len) {
+            Some(index) => {
+                let (offset, free_len) = free.swap_remove(index);
+                if free_len > len {
+                    free.push((offset + len as u64, free_len - len));
+                }
+                offset
+            }
+            None => {
+                let offset = self.end.get();
+                self.end.set(offset + len as u64);
+                offset
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for SpillFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SpillFile")
+            .field("len", &self.end.get())
+            .field("free", &self.free.borrow().len())
+            .finish()
+    }
+}
@@ -0,0 +1,47 @@
+//! counters of the stack switches of a dock, see [`Stack::dock_stats`](crate::Stack::dock_stats)
+
+use std::time::Duration;
+
+/// the number of buckets of [`DockStats::size_histogram`]
+pub const SIZE_BUCKETS: usize = 16;
+
+/// the size of the stacks counted in the first bucket of [`DockStats::size_histogram`]
+const SMALLEST_BUCKET: usize = 128;
+
+/// statistics of the stack switches of a dock, see [`Stack::dock_stats`](crate::Stack::dock_stats)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DockStats {
+    /// stacks suspended, not counting the suspensions the memory budget rejected
+    pub suspends: u64,
+    /// stacks that landed, including the ones whose bytes were still on the dock
+    pub resumes: u64,
+    /// stacks discarded for an entry function, by [`Stack::restart`](crate::Stack::restart) or by resuming a stack that never ran
+    pub restarts: u64,
+    /// entry functions that started running, including the one of the dock
+    pub entry_starts: u64,
+    /// bytes copied out of the dock and landed on it
+    pub bytes_copied: u64,
+    /// the most bytes copied by a single copy
+    pub max_bytes_copied: usize,
+    /// the suspended stacks by size, bucket `i` counts stacks of up to `128 << i` bytes, and the last bucket every bigger one too
+    pub size_histogram: [u64; SIZE_BUCKETS],
+    /// the time spent copying the bytes counted in `bytes_copied`, only measured with the `copy-time` or the `tracing` feature and zero otherwise
+    pub copy_time: Duration,
+}
+
+impl DockStats {
+    pub(crate) fn suspended(&mut self, len: usize) {
+        self.suspends += 1;
+        let bucket = len
+            .div_ceil(SMALLEST_BUCKET)
+            .next_power_of_two()
+            .trailing_zeros() as usize;
+        self.size_histogram[bucket.min(SIZE_BUCKETS - 1)] += 1;
+    }
+
+    pub(crate) fn copied(&mut self, len: usize, elapsed: Duration) {
+        self.bytes_copied += len as u64;
+        self.max_bytes_copied = self.max_bytes_copied.max(len);
+        self.copy_time += elapsed;
+    }
+}
@@ -0,0 +1,113 @@
+//! the bits of the win32 api this crate needs
+
+#![allow(non_snake_case, clippy::upper_case_acronyms)]
+
+use std::ffi::{c_char, c_void};
+
+pub(crate) type BOOL = i32;
+pub(crate) type HANDLE = *mut c_void;
+
+pub(crate) const MEM_COMMIT: u32 = 0x1000;
+pub(crate) const MEM_RESERVE: u32 = 0x2000;
+pub(crate) const MEM_RELEASE: u32 = 0x8000;
+pub(crate) const MEM_WRITE_WATCH: u32 = 0x200000;
+
+pub(crate) const PAGE_NOACCESS: u32 = 0x01;
+pub(crate) const PAGE_READWRITE: u32 = 0x04;
+
+pub(crate) const GENERIC_READ: u32 = 0x80000000;
+pub(crate) const GENERIC_WRITE: u32 = 0x40000000;
+pub(crate) const DELETE: u32 = 0x00010000;
+pub(crate) const FILE_FLAG_DELETE_ON_CLOSE: u32 = 0x04000000;
+
+#[link(name = "kernel32")]
+unsafe extern "system" {
+    pub(crate) fn VirtualAlloc(
+        lpAddress: *mut c_void,
+        dwSize: usize,
+        flAllocationType: u32,
+        flProtect: u32,
+    ) -> *mut c_void;
+    pub(crate) fn VirtualFree(lpAddress: *mut c_void, dwSize: usize, dwFreeType: u32) -> BOOL;
+    pub(crate) fn GetWriteWatch(
+        dwFlags: u32,
+        lpBaseAddress: *mut c_void,
+        dwRegionSize: usize,
+        lpAddresses: *mut *mut c_void,
+        lpdwCount: *mut usize,
+        lpdwGranularity: *mut u32,
+    ) -> u32;
+    pub(crate) fn ResetWriteWatch(lpBaseAddress: *mut c_void, dwRegionSize: usize) -> u32;
+    pub(crate) fn VirtualProtect(
+        lpAddress: *mut c_void,
+        dwSize: usize,
+        flNewProtect: u32,
+        lpflOldProtect: *mut u32,
+    ) -> BOOL;
+}
+
+/// what `SymInitialize` fails with when dbghelp was already initialized for the process
+pub(crate) const ERROR_INVALID_PARAMETER: i32 = 87;
+
+pub(crate) const SYMOPT_UNDNAME: u32 = 0x00000002;
+pub(crate) const SYMOPT_DEFERRED_LOADS: u32 = 0x00000004;
+pub(crate) const SYMOPT_LOAD_LINES: u32 = 0x00000010;
+
+#[repr(C)]
+pub(crate) struct SYMBOL_INFO {
+    pub(crate) SizeOfStruct: u32,
+    pub(crate) TypeIndex: u32,
+    pub(crate) Reserved: [u64; 2],
+    pub(crate) Index: u32,
+    pub(crate) Size: u32,
+    pub(crate) ModBase: u64,
+    pub(crate) Flags: u32,
+    pub(crate) Value: u64,
+    pub(crate) Address: u64,
+    pub(crate) Register: u32,
+    pub(crate) Scope: u32,
+    pub(crate) Tag: u32,
+    pub(crate) NameLen: u32,
+    pub(crate) MaxNameLen: u32,
+    pub(crate) Name: [c_char; 1],
+}
+
+#[repr(C)]
+pub(crate) struct IMAGEHLP_LINE64 {
+    pub(crate) SizeOfStruct: u32,
+    pub(crate) Key: *mut c_void,
+    pub(crate) LineNumber: u32,
+    pub(crate) FileName: *mut c_char,
+    pub(crate) Address: u64,
+}
+
+#[link(name = "kernel32")]
+unsafe extern "system" {
+    pub(crate) fn GetCurrentProcess() -> HANDLE;
+    pub(crate) fn GetCurrentThreadId() -> u32;
+    #[cfg(feature = "asan")]
+    pub(crate) fn GetCurrentThreadStackLimits(LowLimit: *mut usize, HighLimit: *mut usize);
+}
+
+#[link(name = "dbghelp")]
+unsafe extern "system" {
+    pub(crate) fn SymSetOptions(SymOptions: u32) -> u32;
+    pub(crate) fn SymInitialize(
+        hProcess: HANDLE,
+        UserSearchPath: *const c_char,
+        fInvadeProcess: BOOL,
+    ) -> BOOL;
+    pub(crate) fn SymRefreshModuleList(hProcess: HANDLE) -> BOOL;
+    pub(crate) fn SymFromAddr(
+        hProcess: HANDLE,
+        Address: u64,
+        Displacement: *mut u64,
+        Symbol: *mut SYMBOL_INFO,
+    ) -> BOOL;
+    pub(crate) fn SymGetLineFromAddr64(
+        hProcess: HANDLE,
+        qwAddr: u64,
+        pdwDisplacement: *mut u32,
+        Line64: *mut IMAGEHLP_LINE64,
+    ) -> BOOL;
+}
@@ -88,3 +88,1045 @@ fn suspend_and_resume_complex() {
         });
     }
 }
+
+#[test]
+fn park_and_wake_from_another_thread() {
+    unsafe {
+        let res = Stack::dock(|| {
+            println!("park_and_wake_from_another_thread: parking");
+            Stack::park(|handle| {
+                std::thread::spawn(move || {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    println!("park_and_wake_from_another_thread: waking");
+                    handle.wake();
+                });
+            });
+            println!("park_and_wake_from_another_thread: woken");
+            1234i32
+        });
+        assert_eq!(*res, 1234);
+    }
+}
+
+/// a future that is pending once, waking itself from another thread
+struct PendingOnce(bool);
+
+impl std::future::Future for PendingOnce {
+    type Output = ();
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<()> {
+        if std::mem::replace(&mut self.0, true) {
+            return std::task::Poll::Ready(());
+        }
+        let waker = cx.waker().clone();
+        std::thread::spawn(move || waker.wake());
+        std::task::Poll::Pending
+    }
+}
+
+/// a minimal executor, that parks the thread while the future is pending
+fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+    struct Unpark(std::thread::Thread);
+    impl std::task::Wake for Unpark {
+        fn wake(self: std::sync::Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+    let waker = std::task::Waker::from(std::sync::Arc::new(Unpark(std::thread::current())));
+    let mut cx = std::task::Context::from_waker(&waker);
+    let mut fut = std::pin::pin!(fut);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            std::task::Poll::Ready(output) => return output,
+            std::task::Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[test]
+fn await_future_inside_dock() {
+    unsafe {
+        let res = Stack::dock(|| {
+            Stack::await_future(PendingOnce(false));
+            Stack::await_future(async { 1234i32 })
+        });
+        assert_eq!(*res, 1234);
+    }
+}
+
+#[test]
+fn coroutine_future_on_async_executor() {
+    let fut = unsafe {
+        CoroutineFuture::new(|| {
+            println!("coroutine_future_on_async_executor: A");
+            Stack::await_future(PendingOnce(false));
+            println!("coroutine_future_on_async_executor: B");
+            Stack::await_future(PendingOnce(false));
+            println!("coroutine_future_on_async_executor: C");
+            1234i32
+        })
+    };
+    assert_eq!(block_on(async { fut.await + 1 }), 1235);
+}
+
+#[test]
+fn executor_runs_coroutines_on_every_worker() {
+    let finished = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    {
+        let executor = Executor::new(4);
+        for index in 0..16 {
+            let finished = finished.clone();
+            unsafe {
+                executor.spawn(move || {
+                    println!("executor_runs_coroutines_on_every_worker: {index} started");
+                    Stack::yield_now();
+                    Stack::park(|handle| {
+                        std::thread::spawn(move || handle.wake());
+                    });
+                    println!("executor_runs_coroutines_on_every_worker: {index} finished");
+                    finished.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                });
+            }
+        }
+    }
+    assert_eq!(finished.load(std::sync::atomic::Ordering::Relaxed), 16);
+}
+
+#[test]
+fn dock_on_region() {
+    let region = Region::new(64 * 1024).unwrap();
+    let (limit, top) = (region.limit() as usize, region.top() as usize);
+    unsafe {
+        let res = Stack::dock_on(&region, move || {
+            let local = 0u8;
+            let address = &local as *const u8 as usize;
+            assert!((limit..top).contains(&address));
+            Stack::suspend(|stack| Stack::resume(stack));
+            address
+        });
+        println!("dock_on_region: local was at {:#x}, top is {top:#x}", *res);
+    }
+}
+
+#[test]
+fn region_from_raw_aligns_its_top() {
+    let mut memory = vec![0u8; 4096];
+    let base = std::ptr::NonNull::new(memory.as_mut_ptr().wrapping_add(3)).unwrap();
+    unsafe {
+        let region = Region::from_raw(base, 4000).unwrap();
+        println!("region_from_raw_aligns_its_top: {region:?}");
+        assert_eq!(region.top() as usize % 16, 0);
+        assert!(region.top() as usize <= base.as_ptr() as usize + 4000);
+        // nothing is left once the top is aligned
+        let aligned = memory
+            .as_mut_ptr()
+            .wrapping_add(16 - memory.as_ptr() as usize % 16);
+        let tiny = std::ptr::NonNull::new(aligned.wrapping_add(1)).unwrap();
+        assert!(Region::from_raw(tiny, 8).is_err());
+    }
+}
+
+#[test]
+fn resume_on_a_different_dock_fails() {
+    let region = Region::new(64 * 1024).unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    unsafe {
+        Stack::dock(move || {
+            Stack::suspend(move |stack| {
+                let _ = tx.send(stack);
+                Stack::restart(|| ())
+            });
+        });
+        let stack = rx.recv().unwrap();
+        let res = Stack::dock_on(&region, move || match Stack::try_resume(stack) {
+            Err((_, error)) => error,
+        });
+        println!("resume_on_a_different_dock_fails: {res}");
+        assert_eq!(res.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn send_stack_between_threads() {
+    let region = std::sync::Arc::new(SharedRegion::new(64 * 1024).unwrap());
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn({
+        let region = region.clone();
+        move || unsafe {
+            let res = region.dock(move || {
+                println!(
+                    "send_stack_between_threads: started on {:?}",
+                    std::thread::current().id()
+                );
+                Stack::suspend(move |stack| {
+                    let Ok(sent) = SendStack::new(stack) else {
+                        unreachable!("the stack was not spilled")
+                    };
+                    let _ = tx.send(sent);
+                    Stack::restart(|| 0i32)
+                });
+                println!(
+                    "send_stack_between_threads: resumed on {:?}",
+                    std::thread::current().id()
+                );
+                1234i32
+            });
+            assert_eq!(*res, 0);
+        }
+    });
+    let stack = rx.recv().unwrap();
+    let Err(stack) = stack.into_stack() else {
+        panic!("a stack must not land outside of a dock");
+    };
+    unsafe {
+        let res = region.dock(move || match stack.into_stack() {
+            Ok(stack) => Stack::resume(stack),
+            Err(_) => -1i32,
+        });
+        assert_eq!(*res, 1234);
+    }
+}
+
+#[test]
+fn send_stack_keeps_its_name() {
+    let region = std::sync::Arc::new(SharedRegion::new(64 * 1024).unwrap());
+    let (sent, left_behind) = std::thread::spawn({
+        let region = region.clone();
+        move || unsafe {
+            *region.dock(|| {
+                let stack = Stack::builder().name("migrant").from_entry(|| {
+                    let running = Stack::coroutines()
+                        .into_iter()
+                        .find(|info| info.state == CoroutineState::Running);
+                    running.map(|info| (info.name, info.location.unwrap().file().to_owned()))
+                });
+                let Ok(sent) = SendStack::new(stack) else {
+                    unreachable!("the stack was not spilled")
+                };
+                (sent, Stack::coroutines().len())
+            })
+        }
+    })
+    .join()
+    .unwrap();
+    // the coroutine left with its stack
+    assert_eq!(left_behind, 0);
+    unsafe {
+        let res = region.dock(move || match sent.into_stack() {
+            Ok(stack) => Stack::resume(stack),
+            Err(_) => None::<(Option<String>, String)>,
+        });
+        println!("send_stack_keeps_its_name: {res:?}");
+        let (name, file) = res.unwrap();
+        assert_eq!(name.as_deref(), Some("migrant"));
+        assert!(file.ends_with("tests.rs"));
+    }
+}
+
+#[test]
+fn send_spilled_stack_between_threads() {
+    let region = std::sync::Arc::new(SharedRegion::new(64 * 1024).unwrap());
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn({
+        let region = region.clone();
+        move || unsafe {
+            region.dock(move || {
+                let file = Rc::new(SpillFile::temp().unwrap());
+                let frame = std::hint::black_box([5usize; 256]);
+                Stack::suspend(move |mut stack| {
+                    let spilled = stack.spill(&file).is_ok_and(|spilled| spilled);
+                    // the file stays on this thread, the bytes are read back before they leave
+                    let _ = tx.send((spilled, SendStack::new(stack).ok()));
+                    Stack::restart(|| false)
+                });
+                frame.iter().all(|&value| value == 5)
+            });
+        }
+    });
+    let (spilled, sent) = rx.recv().unwrap();
+    assert!(spilled);
+    unsafe {
+        let res = region.dock(move || match sent.map(SendStack::into_stack) {
+            Some(Ok(stack)) => Stack::resume(stack),
+            _ => false,
+        });
+        assert!(*res);
+    }
+}
+
+#[test]
+fn send_deduplicated_stack_between_threads() {
+    let region = std::sync::Arc::new(SharedRegion::new(64 * 1024).unwrap());
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn({
+        let region = region.clone();
+        move || unsafe {
+            region.dock(move || {
+                Stack::set_deduplication(true);
+                let frame = std::hint::black_box([6usize; 256]);
+                Stack::suspend(move |stack| {
+                    let chunked = Stack::chunk_stats().stored_bytes;
+                    // the chunks stay on this thread, the stack gets a buffer of its own before it leaves
+                    let _ = tx.send((chunked, SendStack::new(stack).ok()));
+                    Stack::restart(|| false)
+                });
+                frame.iter().all(|&value| value == 6)
+            });
+        }
+    });
+    let (chunked, sent) = rx.recv().unwrap();
+    println!("send_deduplicated_stack_between_threads: {chunked} bytes in chunks");
+    assert!(chunked > 0);
+    unsafe {
+        let res = region.dock(move || match sent.map(SendStack::into_stack) {
+            Some(Ok(stack)) => Stack::resume(stack),
+            _ => false,
+        });
+        assert!(*res);
+    }
+}
+
+#[test]
+fn send_stack_moves_its_bytes_between_budgets() {
+    let region = std::sync::Arc::new(SharedRegion::new(64 * 1024).unwrap());
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn({
+        let region = region.clone();
+        move || unsafe {
+            region.dock(move || {
+                Stack::set_inline_threshold(0);
+                Stack::suspend(move |stack| {
+                    let held = Stack::suspended_bytes();
+                    let sent = SendStack::new(stack).ok();
+                    let _ = tx.send((held, Stack::suspended_bytes(), sent));
+                    Stack::restart(|| ())
+                });
+            });
+        }
+    });
+    let (held, left_behind, sent) = rx.recv().unwrap();
+    let res = unsafe {
+        region.dock(move || match sent.map(SendStack::into_stack) {
+            Some(Ok(stack)) => {
+                let arrived = Stack::suspended_bytes();
+                drop(stack);
+                (arrived, Stack::suspended_bytes())
+            }
+            _ => (0, usize::MAX),
+        })
+    };
+    let (arrived, dropped) = *res;
+    println!(
+        "send_stack_moves_its_bytes_between_budgets: {held} bytes held, {left_behind} left behind, {arrived} arrived, {dropped} after drop"
+    );
+    assert_ne!(held, 0);
+    assert_eq!(left_behind, 0);
+    assert_eq!(arrived, held);
+    assert_eq!(dropped, 0);
+}
+
+#[test]
+fn suspend_reuses_pooled_buffers() {
+    unsafe {
+        let stats = Stack::dock(|| {
+            // small stacks would be stored inline, without a buffer from the pool
+            Stack::set_inline_threshold(0);
+            for _ in 0..10 {
+                Stack::yield_now();
+            }
+            Stack::pool_stats()
+        });
+        println!("suspend_reuses_pooled_buffers: {stats:?}");
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.hits, 9);
+        assert_eq!(stats.recycled, 10);
+    }
+}
+
+#[test]
+fn suspend_into_caller_buffer() {
+    unsafe {
+        let buffer = Box::leak(Box::new(Vec::with_capacity(64 * 1024)));
+        let (data, capacity) = (buffer.as_ptr() as usize, buffer.capacity());
+        let res = Stack::dock(move || {
+            Stack::suspend_into(buffer, |stack| Stack::resume(stack));
+            (
+                buffer.len(),
+                buffer.as_ptr() as usize,
+                buffer.capacity(),
+                Stack::pool_stats().misses,
+            )
+        });
+        let (len, data_after, capacity_after, misses) = *res;
+        println!("suspend_into_caller_buffer: {len} bytes");
+        assert_ne!(len, 0);
+        // the bytes went into the buffer, which did not have to grow
+        assert_eq!(data_after, data);
+        assert_eq!(capacity_after, capacity);
+        assert_eq!(misses, 0);
+    }
+}
+
+#[test]
+fn small_stacks_are_inline() {
+    unsafe {
+        let res = Stack::dock(|| {
+            let mut seen = Vec::new();
+            for threshold in [0, INLINE_CAPACITY] {
+                Stack::set_inline_threshold(threshold);
+                let seen_ptr = &mut seen as *mut Vec<(usize, usize, bool)>;
+                Stack::suspend(move |stack| {
+                    let (len, inline) = match stack.inner {
+                        StackImpl::Inline { len, .. } => (len, true),
+                        StackImpl::Boxed(ref bytes) => (bytes.len(), false),
+                        _ => (0, false),
+                    };
+                    (*seen_ptr).push((threshold, len, inline));
+                    Stack::resume(stack)
+                });
+            }
+            seen
+        });
+        for &(threshold, len, inline) in res.iter() {
+            println!(
+                "small_stacks_are_inline: threshold {threshold}, {len} bytes, inline: {inline}"
+            );
+            assert_eq!(inline, len <= threshold);
+        }
+    }
+}
+
+/// compares the latency of a suspend and resume with the copy loop of [`asm::resume`] against the byte-wise copy it replaced
+///
+/// ```sh
+/// cargo test -p stack-master --release --target i686-pc-windows-msvc -- --nocapture --ignored switch_latency
+/// ```
+#[test]
+#[ignore = "benchmark"]
+fn switch_latency() {
+    /// recurses until the stack is about `depth` bytes deep, then calls f
+    #[inline(never)]
+    fn deep(depth: usize, f: &mut dyn FnMut()) {
+        let pad = [0u8; 256];
+        std::hint::black_box(&pad);
+        if depth <= 256 {
+            f()
+        } else {
+            deep(depth - 256, f)
+        }
+    }
+
+    unsafe fn resume_bytewise(mut stack: Stack) -> ! {
+        unsafe extern "stdcall" fn recycle(data: *const u8, len: usize, capacity: *mut ()) {
+            unsafe { pool::recycle(Vec::from_raw_parts(data as *mut u8, len, capacity as usize)) }
+        }
+        // the same bookkeeping as `Stack::resume`, so that the iterations do not pile up in the dock
+        stack.unaccount();
+        registry::switch_to(stack.id.take());
+        let StackImpl::Boxed(ref mut bytes) = stack.inner else {
+            unreachable!("the benchmark disables inline stacks");
+        };
+        let mut bytes = std::mem::ManuallyDrop::new(std::mem::take(bytes));
+        unsafe {
+            asm::resume_bytewise(
+                bytes.as_mut_ptr(),
+                bytes.len(),
+                bytes.capacity() as *mut (),
+                recycle,
+            )
+        }
+    }
+
+    let region = Region::new(4 * 1024 * 1024).unwrap();
+    let results = unsafe {
+        Stack::dock_on(&region, || {
+            Stack::set_inline_threshold(0);
+            let mut results = Vec::new();
+            for depth in [
+                256,
+                1024,
+                4096,
+                16 * 1024,
+                64 * 1024,
+                256 * 1024,
+                1024 * 1024,
+            ] {
+                let iterations = (64 * 1024 * 1024 / depth).clamp(100, 100_000) as u32;
+                deep(depth, &mut || {
+                    let mut len = 0usize;
+                    let len_ptr = &mut len as *mut usize;
+                    let mut elapsed = [std::time::Duration::ZERO; 2];
+                    for (bytewise, elapsed) in [true, false].into_iter().zip(&mut elapsed) {
+                        let start = std::time::Instant::now();
+                        for _ in 0..iterations {
+                            Stack::suspend(move |stack| {
+                                if let StackImpl::Boxed(ref bytes) = stack.inner {
+                                    *len_ptr = bytes.len();
+                                }
+                                if bytewise {
+                                    resume_bytewise(stack)
+                                } else {
+                                    Stack::resume(stack)
+                                }
+                            });
+                        }
+                        *elapsed = start.elapsed() / iterations;
+                    }
+                    results.push((len, elapsed));
+                });
+            }
+            results
+        })
+    };
+    for &(len, [bytewise, current]) in results.iter() {
+        println!(
+            "switch_latency: {len:>8} bytes: rep movsb {bytewise:>10?}, current {current:>10?}"
+        );
+    }
+}
+
+#[test]
+fn write_watch_region_keeps_stacks_intact() {
+    /// fills a frame with values derived from `depth`, suspends at the bottom, then checks every frame on the way back up
+    #[inline(never)]
+    fn deep(depth: usize, suspends: usize) -> bool {
+        let mut frame = [0usize; 256];
+        for (index, value) in frame.iter_mut().enumerate() {
+            *value = depth * 1000 + index;
+        }
+        std::hint::black_box(&mut frame);
+        let below = if depth == 0 {
+            for round in 0..suspends {
+                // only the top frame changes between suspends
+                frame[0] = round;
+                std::hint::black_box(&mut frame);
+                unsafe { Stack::yield_now() };
+                if frame[0] != round {
+                    return false;
+                }
+                frame[0] = 0;
+            }
+            true
+        } else {
+            deep(depth - 1, suspends)
+        };
+        below
+            && frame
+                .iter()
+                .enumerate()
+                .all(|(index, &value)| value == depth * 1000 + index)
+    }
+
+    let region = Region::with_write_watch(1024 * 1024).unwrap();
+    unsafe {
+        let res = Stack::dock_on(&region, || deep(64, 16));
+        assert!(*res);
+    }
+}
+
+#[test]
+fn yield_to_itself_copies_nothing() {
+    unsafe {
+        let res = Stack::dock(|| {
+            Stack::set_inline_threshold(0);
+            Stack::set_resume_in_place(true);
+            let mut count = 0;
+            for _ in 0..10 {
+                Stack::yield_now();
+                count += 1;
+            }
+            (count, Stack::pool_stats())
+        });
+        let (count, stats) = *res;
+        println!("yield_to_itself_copies_nothing: {stats:?}");
+        assert_eq!(count, 10);
+        // with an empty run queue the stack lands where it is, without taking a buffer
+        assert_eq!(stats.hits + stats.misses, 0);
+    }
+}
+
+#[test]
+fn resume_in_place_copies_out_when_something_else_lands() {
+    unsafe {
+        let res = Stack::dock(|| {
+            Stack::set_inline_threshold(0);
+            Stack::set_resume_in_place(true);
+            // yield back and forth, so that every stack left on the dock has to be copied out before the other one lands
+            Stack::schedule(Stack::from_entry(|| {
+                loop {
+                    Stack::yield_now();
+                }
+            }));
+            let frame = std::hint::black_box([7usize; 64]);
+            for _ in 0..10 {
+                Stack::yield_now();
+            }
+            (frame.iter().all(|&value| value == 7), Stack::pool_stats())
+        });
+        let (intact, stats) = *res;
+        println!("resume_in_place_copies_out_when_something_else_lands: {stats:?}");
+        assert!(intact);
+        // one miss for each of the two coroutines, then they reuse the buffers of each other
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.hits, 18);
+        assert_eq!(stats.recycled, 19);
+    }
+}
+
+#[test]
+fn compressed_stacks_resume_intact() {
+    #[inline(never)]
+    fn padded(sizes: &mut (usize, usize)) -> bool {
+        let mut frame = [0u32; 4096];
+        frame[7] = 1234;
+        std::hint::black_box(&mut frame);
+        let sizes = sizes as *mut (usize, usize);
+        unsafe {
+            Stack::suspend(move |mut stack| {
+                let len = stack.len();
+                stack.compress(&Lz);
+                if let StackImpl::Compressed { ref bytes, .. } = stack.inner {
+                    *sizes = (len, bytes.len());
+                }
+                Stack::resume(stack)
+            });
+            // parked stacks are compressed too
+            Stack::set_compression(Some(&Lz), 0);
+            Stack::park(|handle| handle.wake());
+        }
+        frame[7] == 1234 && frame.iter().filter(|&&value| value != 0).count() == 1
+    }
+
+    unsafe {
+        let res = Stack::dock(|| {
+            let mut sizes = (0, 0);
+            (padded(&mut sizes), sizes)
+        });
+        let (intact, (len, compressed)) = *res;
+        println!("compressed_stacks_resume_intact: {len} bytes compressed to {compressed}");
+        assert!(intact);
+        assert!(compressed < len / 4);
+    }
+}
+
+#[test]
+fn spilled_stacks_resume_intact() {
+    /// fills a frame with values derived from `seed`, parks a few times, then checks the frame
+    #[inline(never)]
+    fn filled(seed: usize) -> bool {
+        let mut frame = [0usize; 1024];
+        for (index, value) in frame.iter_mut().enumerate() {
+            *value = seed * 7919 + index;
+        }
+        std::hint::black_box(&mut frame);
+        for _ in 0..3 {
+            unsafe { Stack::park(|handle| handle.wake()) };
+        }
+        frame
+            .iter()
+            .enumerate()
+            .all(|(index, &value)| value == seed * 7919 + index)
+    }
+
+    let file = Rc::new(SpillFile::temp().unwrap());
+    let spill_file = file.clone();
+    unsafe {
+        let res = Stack::dock(move || {
+            // every parked stack goes to the file
+            Stack::set_spill_budget(Some(spill_file), 0);
+            // how many coroutines finished, and how many of those found their frame intact
+            let results = Rc::new(std::cell::Cell::new((0, 0)));
+            for seed in 0..3 {
+                let results = results.clone();
+                Stack::schedule(Stack::from_entry(move || {
+                    let intact = filled(seed) as usize;
+                    let (finished, intact_before) = results.get();
+                    results.set((finished + 1, intact_before + intact));
+                    Stack::run_next(|| (0, 0))
+                }));
+            }
+            while results.get().0 < 3 {
+                Stack::yield_now();
+            }
+            results.get()
+        });
+        println!("spilled_stacks_resume_intact: {file:?}");
+        assert_eq!(*res, (3, 3));
+        assert!(!file.is_empty());
+    }
+}
+
+#[test]
+fn parked_live_stacks_leave_the_spill_budget() {
+    let file = Rc::new(SpillFile::temp().unwrap());
+    let spill_file = file.clone();
+    unsafe {
+        let res = Stack::dock(move || {
+            Stack::set_resume_in_place(true);
+            // far more than the one stack parked at a time takes, far less than every park together
+            Stack::set_spill_budget(Some(spill_file), 64 * 1024);
+            let slot = Rc::new(std::cell::Cell::new(None::<WakeHandle>));
+            let waker = slot.clone();
+            Stack::schedule(Stack::from_entry(move || {
+                loop {
+                    if let Some(handle) = waker.take() {
+                        handle.wake();
+                    }
+                    Stack::yield_now();
+                }
+            }));
+            for _ in 0..10_000 {
+                let slot = slot.clone();
+                Stack::park(move |handle| slot.set(Some(handle)));
+            }
+            Stack::suspended_bytes()
+        });
+        println!(
+            "parked_live_stacks_leave_the_spill_budget: {} bytes suspended, {file:?}",
+            *res
+        );
+        assert!(file.is_empty());
+    }
+}
+
+#[test]
+fn deduplicated_stacks_share_chunks() {
+    /// a big frame that only differs by `seed`, yields a few times, then checks the frame
+    #[inline(never)]
+    fn mostly_zeros(seed: usize) -> bool {
+        let mut frame = [0usize; 1024];
+        frame[0] = seed;
+        std::hint::black_box(&mut frame);
+        for _ in 0..3 {
+            unsafe { Stack::yield_now() };
+        }
+        frame[0] == seed && frame[1..].iter().all(|&value| value == 0)
+    }
+
+    unsafe {
+        let res = Stack::dock(|| {
+            Stack::set_inline_threshold(0);
+            Stack::set_deduplication(true);
+            // how many coroutines finished, and how many of those found their frame intact
+            let results = Rc::new(std::cell::Cell::new((0, 0)));
+            for seed in 0..4 {
+                let results = results.clone();
+                Stack::schedule(Stack::from_entry(move || {
+                    let intact = mostly_zeros(seed) as usize;
+                    let (finished, intact_before) = results.get();
+                    results.set((finished + 1, intact_before + intact));
+                    Stack::run_next(|| ((0, 0), ChunkStats::default()))
+                }));
+            }
+            while results.get().0 < 4 {
+                Stack::yield_now();
+            }
+            (results.get(), Stack::chunk_stats())
+        });
+        let (results, stats) = *res;
+        println!("deduplicated_stacks_share_chunks: {stats:?}");
+        assert_eq!(results, (4, 4));
+        assert!(stats.shared_bytes > stats.stored_bytes);
+    }
+}
+
+#[test]
+fn memory_budget_policies() {
+    /// suspends with a big frame, returning the suspended bytes of the dock while suspended
+    #[inline(never)]
+    fn held_while_suspended() -> usize {
+        let frame = [0usize; 1024];
+        std::hint::black_box(&frame);
+        let mut held = 0;
+        let held_ptr = &mut held as *mut usize;
+        unsafe {
+            Stack::suspend(move |stack| {
+                let stack = stack.into_owned();
+                *held_ptr = Stack::suspended_bytes();
+                Stack::resume(stack)
+            });
+        }
+        held
+    }
+
+    unsafe {
+        let res = Stack::dock(|| {
+            Stack::set_inline_threshold(0);
+            let calls = Rc::new(std::cell::Cell::new(0));
+            let policy_calls = calls.clone();
+            Stack::set_memory_budget(0, move |_| {
+                policy_calls.set(policy_calls.get() + 1);
+                Pressure::Reject
+            });
+            let rejected = Stack::try_suspend(|stack| Stack::resume(stack));
+            Stack::set_memory_budget(0, |_| Pressure::Compress(&Lz));
+            let compressed = held_while_suspended();
+            Stack::clear_memory_budget();
+            let plain = held_while_suspended();
+            (
+                rejected,
+                calls.get(),
+                compressed,
+                plain,
+                Stack::suspended_bytes(),
+            )
+        });
+        let (rejected, calls, compressed, plain, after) = *res;
+        println!("memory_budget_policies: {compressed} bytes compressed, {plain} bytes plain");
+        assert_eq!(rejected, Err(BudgetExceeded));
+        assert_eq!(calls, 1);
+        assert!(compressed < plain);
+        assert_eq!(after, 0);
+    }
+}
+
+#[test]
+fn memory_budget_fits_a_single_stack() {
+    /// suspends with a big frame, returning the length of the suspended stack
+    #[inline(never)]
+    fn suspend_once() -> (usize, Result<(), BudgetExceeded>) {
+        let frame = [0usize; 256];
+        std::hint::black_box(&frame);
+        let mut len = 0;
+        let len_ptr = &mut len as *mut usize;
+        let res = unsafe {
+            Stack::try_suspend(move |stack| {
+                *len_ptr = stack.len();
+                Stack::resume(stack)
+            })
+        };
+        (len, res)
+    }
+
+    unsafe {
+        let res = Stack::dock(|| {
+            Stack::set_inline_threshold(0);
+            let (len, _) = suspend_once();
+            let calls = Rc::new(std::cell::Cell::new(0));
+            let policy_calls = calls.clone();
+            // room for one stack, but not for the same stack counted twice
+            Stack::set_memory_budget(len + len / 2, move |_| {
+                policy_calls.set(policy_calls.get() + 1);
+                Pressure::Reject
+            });
+            let (_, fits) = suspend_once();
+            (len, fits, calls.get())
+        });
+        let (len, fits, calls) = *res;
+        println!("memory_budget_fits_a_single_stack: {len} bytes");
+        assert_eq!(fits, Ok(()));
+        assert_eq!(calls, 0);
+    }
+}
+
+#[test]
+fn inspect_suspended_stack() {
+    type Seen = (usize, usize, *const u8, Vec<Frame>);
+
+    #[inline(never)]
+    fn outer(seen: *mut Seen) {
+        inner(seen);
+        std::hint::black_box(());
+    }
+
+    #[inline(never)]
+    fn inner(seen: *mut Seen) {
+        unsafe {
+            Stack::suspend(move |stack| {
+                *seen = (
+                    stack.len(),
+                    stack.as_bytes().map_or(0, |bytes| bytes.len()),
+                    stack.landing_address(),
+                    stack.frames().collect(),
+                );
+                Stack::resume(stack)
+            });
+        }
+        std::hint::black_box(());
+    }
+
+    unsafe {
+        let res = Stack::dock(|| {
+            let mut seen: Seen = (0, 0, std::ptr::null(), Vec::new());
+            outer(&mut seen);
+            (seen, asm::current_stack_start())
+        });
+        let ((len, bytes, landing, ref frames), dock) = *res;
+        println!("inspect_suspended_stack: {len} bytes, landing at {landing:?}");
+        for frame in frames {
+            println!("inspect_suspended_stack: {frame:?}");
+        }
+        assert_eq!(len, bytes);
+        assert_eq!(landing, dock);
+        // at least the frames of suspend, inner and outer
+        assert!(frames.len() >= 3);
+        for frame in frames {
+            let frame_pointer = frame.frame_pointer as usize;
+            assert!(frame_pointer < landing as usize && frame_pointer >= landing as usize - len);
+        }
+    }
+}
+
+#[test]
+fn backtrace_of_suspended_stack() {
+    #[inline(never)]
+    fn waiting_here(backtrace: *mut Backtrace) {
+        unsafe {
+            Stack::suspend(move |stack| {
+                *backtrace = stack.backtrace();
+                Stack::resume(stack)
+            });
+        }
+        std::hint::black_box(());
+    }
+
+    // std initializes dbghelp for the process first, the backtrace must still be symbolized
+    let _ = std::backtrace::Backtrace::force_capture().to_string();
+    unsafe {
+        let res = Stack::dock(|| {
+            let mut backtrace = Backtrace::default();
+            waiting_here(&mut backtrace);
+            backtrace
+        });
+        println!("backtrace_of_suspended_stack:\n{res}");
+        assert!(res.frames.iter().any(|frame| {
+            frame
+                .function
+                .as_deref()
+                .is_some_and(|function| function.contains("waiting_here"))
+        }));
+    }
+}
+
+#[test]
+fn stacks_only_get_an_id_inside_a_dock() {
+    let outside = unsafe { Stack::from_entry(|| ()) };
+    println!(
+        "stacks_only_get_an_id_inside_a_dock: {:?} outside",
+        outside.id()
+    );
+    assert_eq!(outside.id(), None);
+    unsafe {
+        let res = Stack::dock(|| {
+            let inside = Stack::from_entry(|| ());
+            let listed = Stack::coroutines()
+                .iter()
+                .any(|info| Some(info.id) == inside.id());
+            (inside.id(), listed)
+        });
+        let (id, listed) = *res;
+        println!("stacks_only_get_an_id_inside_a_dock: {id:?} inside");
+        assert!(id.is_some());
+        assert!(listed);
+    }
+}
+
+#[test]
+fn registry_lists_coroutines() {
+    unsafe {
+        let res = Stack::dock(|| {
+            let waiting = Stack::builder().name("waiting").from_entry(|| {
+                loop {
+                    Stack::yield_now();
+                }
+            });
+            let waiting_id = waiting.id();
+            let never_started = Stack::builder().name("never started").from_entry(|| ());
+            let never_started_id = never_started.id();
+            Stack::schedule(waiting);
+            // runs `waiting` until it yields back
+            Stack::yield_now();
+            let before_drop = Stack::coroutines();
+            drop(never_started);
+            let mut dump = Vec::new();
+            Stack::dump_coroutines(&mut dump).unwrap();
+            (
+                waiting_id,
+                never_started_id,
+                before_drop,
+                Stack::coroutines(),
+                String::from_utf8(dump).unwrap(),
+            )
+        });
+        let (waiting_id, never_started_id, ref before_drop, ref after_drop, ref dump) = *res;
+        println!("registry_lists_coroutines:\n{dump}");
+        let find =
+            |list: &[CoroutineInfo], id| list.iter().find(|info| Some(info.id) == id).cloned();
+        let waiting = find(before_drop, waiting_id).unwrap();
+        assert_eq!(waiting.name.as_deref(), Some("waiting"));
+        assert_eq!(waiting.state, CoroutineState::Suspended);
+        assert_ne!(waiting.stack_size, 0);
+        assert!(waiting.location.unwrap().file().ends_with("tests.rs"));
+        let never_started = find(before_drop, never_started_id).unwrap();
+        assert_eq!(never_started.state, CoroutineState::NeverStarted);
+        // the dock's own entry function is running
+        assert!(
+            before_drop
+                .iter()
+                .any(|info| info.state == CoroutineState::Running)
+        );
+        assert!(find(after_drop, never_started_id).is_none());
+        assert!(dump.contains("\"waiting\""));
+    }
+}
+
+#[test]
+fn poison_and_scan_vacated_stacks() {
+    unsafe {
+        let res = Stack::dock(|| {
+            Stack::set_poison(true);
+            let holder = Box::into_raw(Box::new([0usize; 4]));
+            let scanned = Stack::scan_for_escapes(holder as *const u8, size_of::<[usize; 4]>());
+            let seen = Rc::new(std::cell::Cell::new(0u32));
+            let local = std::hint::black_box(1234u32);
+            // the misuse: a pointer to a local outlives the suspension
+            (*holder)[1] = &local as *const u32 as usize;
+            let seen_in_callback = seen.clone();
+            Stack::suspend(move |stack| {
+                seen_in_callback.set(((*holder)[1] as *const u32).read_volatile());
+                Stack::resume(stack)
+            });
+            drop(scanned);
+            let holder = Box::from_raw(holder);
+            (local, seen.get(), Stack::take_escapes(), holder[1])
+        });
+        let (local, seen, ref escapes, pointer) = *res;
+        println!("poison_and_scan_vacated_stacks: saw {seen:#x}, {escapes:?}");
+        assert_eq!(local, 1234);
+        assert_eq!(seen, u32::from_ne_bytes([Stack::POISON; 4]));
+        assert_eq!(escapes.len(), 1);
+        assert_eq!(escapes[0].offset, size_of::<usize>());
+        assert_eq!(escapes[0].pointer as usize, pointer);
+    }
+}
+
+#[test]
+fn dock_stats_count_switches() {
+    unsafe {
+        let res = Stack::dock(|| {
+            Stack::schedule(Stack::from_entry(|| {
+                loop {
+                    Stack::yield_now();
+                }
+            }));
+            for _ in 0..8 {
+                Stack::yield_now();
+            }
+            Stack::dock_stats()
+        });
+        let stats = *res;
+        println!("dock_stats_count_switches: {stats:?}");
+        assert!(stats.suspends >= 8);
+        assert!(stats.resumes >= 8);
+        // the dock's own entry function and the scheduled one
+        assert_eq!(stats.entry_starts, 2);
+        assert!(stats.bytes_copied > 0);
+        assert!(stats.max_bytes_copied > 0);
+        assert_eq!(stats.size_histogram.iter().sum::<u64>(), stats.suspends);
+        // the clock is not read on the switches without the features that time them
+        #[cfg(not(any(feature = "copy-time", feature = "tracing")))]
+        assert_eq!(stats.copy_time, std::time::Duration::ZERO);
+    }
+}
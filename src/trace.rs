@@ -0,0 +1,174 @@
+//! the instrumentation of the stack switches, they are counted in the [`DockStats`](crate::DockStats) of the dock,
+//! and with the `tracing` feature they are also events and spans for the `tracing` crate
+//!
+//! every coroutine gets a `coroutine` span, entered while it runs and exited while it is suspended, so that what it logs is attributed to it,
+//! and every suspension, landing, restart and start is a `trace` event with the id of the coroutine, the bytes copied and the time the copy took
+//!
+//! the copies are only timed with the `copy-time` or the `tracing` feature, reading the clock twice per switch is not free
+
+use std::time::Duration;
+#[cfg(any(feature = "copy-time", feature = "tracing"))]
+use std::time::Instant;
+
+use crate::{CoroutineId, dock};
+
+/// the spans of the coroutines of a dock
+#[derive(Default)]
+pub(crate) struct Spans {
+    #[cfg(feature = "tracing")]
+    spans: std::collections::BTreeMap<CoroutineId, tracing::Span>,
+    /// the span of the running coroutine
+    #[cfg(feature = "tracing")]
+    entered: Option<(CoroutineId, tracing::span::EnteredSpan)>,
+}
+
+impl Spans {
+    pub(crate) fn created(&mut self, id: CoroutineId, name: Option<&str>) {
+        #[cfg(feature = "tracing")]
+        self.spans
+            .insert(id, tracing::trace_span!("coroutine", %id, name));
+        #[cfg(not(feature = "tracing"))]
+        let _ = (id, name);
+    }
+
+    /// the coroutine runs, `None` for one that was never registered
+    pub(crate) fn enter(&mut self, id: Option<CoroutineId>) {
+        self.exit();
+        #[cfg(feature = "tracing")]
+        if let Some(id) = id {
+            let span = self
+                .spans
+                .remove(&id)
+                .unwrap_or_else(|| tracing::trace_span!("coroutine", %id));
+            self.entered = Some((id, span.entered()));
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = id;
+    }
+
+    /// the running coroutine was suspended or finished
+    pub(crate) fn exit(&mut self) {
+        #[cfg(feature = "tracing")]
+        if let Some((id, entered)) = self.entered.take() {
+            self.spans.insert(id, entered.exit());
+        }
+    }
+
+    pub(crate) fn remove(&mut self, id: CoroutineId) {
+        #[cfg(feature = "tracing")]
+        if self
+            .entered
+            .as_ref()
+            .is_some_and(|(entered, _)| *entered == id)
+        {
+            self.entered = None;
+        } else {
+            self.spans.remove(&id);
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = id;
+    }
+}
+
+/// the span of a call to [`Stack::dock`](crate::Stack::dock), entered until it is dropped
+pub(crate) struct DockSpan {
+    #[cfg(feature = "tracing")]
+    _entered: tracing::span::EnteredSpan,
+}
+
+pub(crate) fn dock() -> DockSpan {
+    DockSpan {
+        #[cfg(feature = "tracing")]
+        _entered: tracing::debug_span!("dock").entered(),
+    }
+}
+
+/// measures how long a copy of the bytes of a stack takes, measures nothing without the features that need it
+pub(crate) struct CopyTimer {
+    #[cfg(any(feature = "copy-time", feature = "tracing"))]
+    start: Instant,
+}
+
+impl CopyTimer {
+    pub(crate) fn start() -> Self {
+        CopyTimer {
+            #[cfg(any(feature = "copy-time", feature = "tracing"))]
+            start: Instant::now(),
+        }
+    }
+
+    fn elapsed(self) -> Duration {
+        #[cfg(any(feature = "copy-time", feature = "tracing"))]
+        return self.start.elapsed();
+        #[cfg(not(any(feature = "copy-time", feature = "tracing")))]
+        Duration::ZERO
+    }
+}
+
+#[cfg(any(feature = "copy-time", feature = "tracing"))]
+thread_local! {
+    /// when the stack that is landing started to be copied, see [`landing`]
+    static LANDING: std::cell::Cell<Option<Instant>> = const { std::cell::Cell::new(None) };
+}
+
+/// the coroutine was suspended into a stack of `len` bytes, its bytes may not be copied yet
+///
+/// called with the dock borrowed, so the caller counts it
+pub(crate) fn suspended(id: CoroutineId, len: usize) {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(coroutine = %id, bytes = len, "suspend");
+    #[cfg(not(feature = "tracing"))]
+    let _ = (id, len);
+}
+
+/// the `len` bytes of a suspended stack were copied out of the dock
+pub(crate) fn copied_out(len: usize, timer: CopyTimer) {
+    let elapsed = timer.elapsed();
+    dock::try_with(|dock| dock.stats.copied(len, elapsed));
+    #[cfg(feature = "tracing")]
+    tracing::trace!(bytes = len, ?elapsed, "copy out");
+}
+
+/// the stack of the coroutine is about to land, the copy is timed until [`landed`]
+pub(crate) fn landing(id: Option<CoroutineId>) {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(coroutine = id.map(tracing::field::display), "resume");
+    #[cfg(not(feature = "tracing"))]
+    let _ = id;
+    #[cfg(any(feature = "copy-time", feature = "tracing"))]
+    LANDING.set(Some(Instant::now()));
+}
+
+/// the `len` bytes of the stack that was landing are on the dock, `len` is zero if they were already there
+///
+/// always called after [`landing`]
+pub(crate) fn landed(len: usize) {
+    #[cfg(any(feature = "copy-time", feature = "tracing"))]
+    let elapsed = LANDING
+        .take()
+        .map_or(Duration::ZERO, |start| start.elapsed());
+    #[cfg(not(any(feature = "copy-time", feature = "tracing")))]
+    let elapsed = Duration::ZERO;
+    dock::try_with(|dock| {
+        dock.stats.resumes += 1;
+        dock.stats.copied(len, elapsed);
+    });
+    #[cfg(feature = "tracing")]
+    tracing::trace!(bytes = len, ?elapsed, "land");
+}
+
+/// the current stack is discarded for an entry function
+pub(crate) fn restarted(id: Option<CoroutineId>) {
+    dock::try_with(|dock| dock.stats.restarts += 1);
+    #[cfg(feature = "tracing")]
+    tracing::trace!(coroutine = id.map(tracing::field::display), "restart");
+    #[cfg(not(feature = "tracing"))]
+    let _ = id;
+}
+
+/// an entry function started running
+pub(crate) fn started() {
+    dock::try_with(|dock| dock.stats.entry_starts += 1);
+    #[cfg(feature = "tracing")]
+    tracing::trace!("start");
+}
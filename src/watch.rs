@@ -0,0 +1,107 @@
+//! copying only the dirty pages of a stack when it is suspended again, for docks on regions with a write watch
+
+use std::ffi::c_void;
+
+use crate::region::Region;
+use crate::sys;
+
+/// the write watch of the region of a dock, along with the buffer of the stack that landed last
+pub(crate) struct WriteWatch {
+    limit: *mut u8,
+    top: *mut u8,
+    /// the buffer the running stack landed from, its last `len` bytes are the bytes of the stack as they were when it landed
+    ///
+    /// `None` if the running stack did not land from such a buffer
+    snapshot: Option<Box<[u8]>>,
+    len: usize,
+    /// reused for the addresses of the dirty pages
+    dirty: Vec<*mut c_void>,
+}
+
+impl WriteWatch {
+    pub(crate) fn new(region: &Region) -> Self {
+        WriteWatch {
+            limit: region.limit(),
+            top: region.top(),
+            snapshot: None,
+            len: 0,
+            dirty: Vec::new(),
+        }
+    }
+
+    /// called once a stack landed from `bytes`, which are now the same as the dock
+    pub(crate) fn landed(&mut self, bytes: Box<[u8]>, len: usize) {
+        unsafe {
+            sys::ResetWriteWatch(self.limit as _, self.top as usize - self.limit as usize);
+        }
+        self.snapshot = Some(bytes);
+        self.len = len;
+    }
+
+    /// called when something other than the snapshot lands or restarts, the snapshot no longer matches the running stack
+    pub(crate) fn forget(&mut self) {
+        self.snapshot = None;
+    }
+
+    /// copies the stack between `stack_data` and the dock into a buffer whose last `stack_len` bytes are the stack
+    ///
+    /// if the running stack landed from a snapshot that is big enough, only the pages written to since then are copied into it
+    pub(crate) unsafe fn capture(&mut self, stack_data: *const u8, stack_len: usize) -> Box<[u8]> {
+        let start = unsafe { stack_data.add(stack_len) };
+        let Some(mut bytes) = self
+            .snapshot
+            .take()
+            .filter(|bytes| bytes.len() >= stack_len)
+        else {
+            // leave some room below the stack, so it can grow without another full copy
+            let mut bytes =
+                vec![0; stack_len.next_multiple_of(Region::PAGE_SIZE)].into_boxed_slice();
+            let offset = bytes.len() - stack_len;
+            bytes[offset..]
+                .copy_from_slice(unsafe { std::slice::from_raw_parts(stack_data, stack_len) });
+            return bytes;
+        };
+        // `bytes[bytes.len() - (start - address)]` is the byte at `address`
+        let end = bytes.len();
+        let mut copy = |from: *const u8, to: *const u8| {
+            let len = to as usize - from as usize;
+            let offset = end - (start as usize - from as usize);
+            bytes[offset..offset + len]
+                .copy_from_slice(unsafe { std::slice::from_raw_parts(from, len) });
+        };
+        // the part of the stack that is deeper than it was when it landed is all new
+        let old_data = unsafe { start.sub(self.len) };
+        if stack_data < old_data {
+            copy(stack_data, old_data);
+        }
+        let from = stack_data.max(old_data);
+        if from < start {
+            let first_page = (from as usize & !(Region::PAGE_SIZE - 1)) as *mut u8;
+            let pages = (start as usize - first_page as usize).div_ceil(Region::PAGE_SIZE);
+            self.dirty.resize(pages, std::ptr::null_mut());
+            let mut count = pages;
+            let mut granularity = 0;
+            let result = unsafe {
+                sys::GetWriteWatch(
+                    0,
+                    first_page as _,
+                    start as usize - first_page as usize,
+                    self.dirty.as_mut_ptr(),
+                    &mut count,
+                    &mut granularity,
+                )
+            };
+            if result != 0 {
+                // the query failed, so every page counts as dirty
+                copy(from, start);
+            } else {
+                for &page in &self.dirty[..count] {
+                    let page = page as *const u8;
+                    let page_end = page.wrapping_add(Region::PAGE_SIZE);
+                    copy(page.max(from), page_end.min(start));
+                }
+            }
+        }
+        bytes
+    }
+}